@@ -0,0 +1,1374 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error;
+use std::fmt;
+use std::io;
+use std::rc::Rc;
+
+use crate::opcode::{Operand, Pair, Reg, OPCODES};
+
+/// The output of a successful assembly: a contiguous image starting at
+/// `origin`, ready for `Cpu::load_into_rom`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Assembly {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+    pub symbols: BTreeMap<String, u16>,
+    /// The address given to `END`, if any.
+    pub start: Option<u16>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    /// The included file containing the error, or `None` for the main source.
+    pub file: Option<String>,
+    pub line: usize,
+    pub kind: ErrorKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    Syntax(String),
+    UnknownInstruction(String),
+    InvalidOperands(String),
+    UndefinedSymbol(String),
+    DuplicateSymbol(String),
+    OutOfRange(i32),
+    Include(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.file {
+            Some(ref file) => write!(f, "{}:{}: {}", file, self.line, self.kind),
+            None => write!(f, "line {}: {}", self.line, self.kind),
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorKind::Syntax(ref message) => write!(f, "{}", message),
+            ErrorKind::UnknownInstruction(ref name) => write!(f, "unknown instruction {}", name),
+            ErrorKind::InvalidOperands(ref name) => write!(f, "invalid operands for {}", name),
+            ErrorKind::UndefinedSymbol(ref name) => write!(f, "undefined symbol {}", name),
+            ErrorKind::DuplicateSymbol(ref name) => write!(f, "{} is already defined", name),
+            ErrorKind::OutOfRange(value) => write!(f, "value {} is out of range", value),
+            ErrorKind::Include(ref message) => write!(f, "cannot include {}", message),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+fn syntax<T>(message: &str) -> Result<T, ErrorKind> {
+    Err(ErrorKind::Syntax(message.to_owned()))
+}
+
+/// Assembles Intel-syntax 8080 source.
+///
+/// Labels end with a colon, or may stand alone at the start of a line; a
+/// label beginning with `.` is local to the preceding ordinary label.
+/// Symbols and mnemonics are case-insensitive. Supported directives are
+/// `ORG`, `EQU`, `SET`, `DB`, `DW`, `DS` and `END`, along with the Digital
+/// Research MAC directives `MACRO`/`LOCAL`/`EXITM`/`ENDM`, `REPT`, `IRP`,
+/// `IRPC`, `IF`/`ELSE`/`ENDIF`, `INCLUDE` and `MACLIB`. Since this function
+/// has no way to read files, including one is an error; see `assemble_with`.
+pub fn assemble(source: &str) -> Result<Assembly, Error> {
+    assemble_with(source, |name| {
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("{} was not found", name)))
+    })
+}
+
+/// Assembles source that may include other files. `include` returns the
+/// contents of the named file; `MACLIB NAME` includes `NAME.LIB`.
+pub fn assemble_with<F>(source: &str, mut include: F) -> Result<Assembly, Error>
+    where F: FnMut(&str) -> io::Result<String>
+{
+    let lines = split(source, None);
+    let mut assembler = Assembler::new(&mut include);
+
+    for pass in 1..=2 {
+        assembler.begin(pass);
+        assembler.block(&lines)?;
+    }
+
+    Ok(assembler.finish())
+}
+
+/// A line of source and where it came from. Lines produced by expanding a
+/// macro carry the location of the invocation.
+#[derive(Clone, Debug)]
+struct Line {
+    text: String,
+    file: Option<Rc<str>>,
+    number: usize,
+}
+
+impl Line {
+    fn error(&self, kind: ErrorKind) -> Error {
+        Error { file: self.file.as_deref().map(String::from), line: self.number, kind }
+    }
+
+    fn syntax(&self, message: &str) -> Error {
+        self.error(ErrorKind::Syntax(message.to_owned()))
+    }
+
+    fn expanded(&self, text: String) -> Line {
+        Line { text, file: self.file.clone(), number: self.number }
+    }
+}
+
+fn split(source: &str, file: Option<Rc<str>>) -> Rc<[Line]> {
+    source.lines()
+        .enumerate()
+        .map(|(index, text)| Line { text: text.to_owned(), file: file.clone(), number: index + 1 })
+        .collect()
+}
+
+#[derive(Debug)]
+struct Macro {
+    parameters: Vec<String>,
+    body: Rc<[Line]>,
+}
+
+struct Condition {
+    /// Whether every enclosing block is being assembled.
+    enclosing: bool,
+    active: bool,
+    seen_else: bool,
+}
+
+enum Flow {
+    Next,
+    /// `EXITM` ends the innermost macro expansion.
+    Exit,
+    End,
+}
+
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Number(i32),
+    Str(Vec<u8>),
+    Ident(String),
+    Dollar,
+    Op(&'static str),
+}
+
+const OPERATORS: [&str; 14] = [
+    "<>", "<=", ">=", "+", "-", "*", "/", "(", ")", ",", ":", "=", "<", ">",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, ErrorKind> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c == ';' {
+            break;
+        } else if c == '\'' || c == '"' {
+            let mut string = Vec::new();
+            let mut chars = rest.char_indices().skip(1).peekable();
+
+            loop {
+                match chars.next() {
+                    Some((_, q)) if q == c && chars.peek().map(|&(_, n)| n) == Some(c) => {
+                        chars.next();
+                        string.push(c as u8);
+                    }
+                    Some((i, q)) if q == c => {
+                        rest = &rest[i + 1..];
+                        break;
+                    }
+                    Some((_, ch)) if ch.is_ascii() => string.push(ch as u8),
+                    Some(_) => return syntax("strings must be ASCII"),
+                    None => return syntax("unterminated string"),
+                }
+            }
+
+            tokens.push(Token::Str(string));
+        } else if c.is_ascii_digit() {
+            let end = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            tokens.push(Token::Number(number(&rest[..end])?));
+            rest = &rest[end..];
+        } else if is_symbol_start(c) {
+            let end = rest.find(|c: char| !is_symbol_char(c)).unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_ascii_uppercase()));
+            rest = &rest[end..];
+        } else if c == '$' {
+            tokens.push(Token::Dollar);
+            rest = &rest[1..];
+        } else {
+            let op = OPERATORS.iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| ErrorKind::Syntax(format!("unexpected character '{}'", c)))?;
+
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn is_symbol_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || matches!(c, '_' | '.' | '?' | '@')
+}
+
+fn is_symbol_char(c: char) -> bool {
+    is_symbol_start(c) || c.is_ascii_digit() || c == '$'
+}
+
+fn number(text: &str) -> Result<i32, ErrorKind> {
+    let text = text.to_ascii_uppercase();
+    let (digits, radix) = match text.as_bytes()[text.len() - 1] {
+        b'H' => (&text[..text.len() - 1], 16),
+        b'B' => (&text[..text.len() - 1], 2),
+        b'O' | b'Q' => (&text[..text.len() - 1], 8),
+        b'D' => (&text[..text.len() - 1], 10),
+        _ => (&text[..], 10),
+    };
+
+    u16::from_str_radix(digits, radix)
+        .map(i32::from)
+        .map_err(|_| ErrorKind::Syntax(format!("invalid number {}", text)))
+}
+
+fn register(tokens: &[Token]) -> Option<Reg> {
+    let reg = match tokens {
+        [Token::Ident(name)] => match &name[..] {
+            "B" => Reg::B,
+            "C" => Reg::C,
+            "D" => Reg::D,
+            "E" => Reg::E,
+            "H" => Reg::H,
+            "L" => Reg::L,
+            "M" => Reg::M,
+            "A" => Reg::A,
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    Some(reg)
+}
+
+fn pair(tokens: &[Token]) -> Option<Pair> {
+    let pair = match tokens {
+        [Token::Ident(name)] => match &name[..] {
+            "B" => Pair::B,
+            "D" => Pair::D,
+            "H" => Pair::H,
+            "SP" => Pair::Sp,
+            "PSW" => Pair::Psw,
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    Some(pair)
+}
+
+/// Splits operands at the commas outside of parentheses.
+fn operands(tokens: &[Token]) -> Vec<&[Token]> {
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+
+    let mut operands = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, token) in tokens.iter().enumerate() {
+        match *token {
+            Token::Op("(") => depth += 1,
+            Token::Op(")") => depth -= 1,
+            Token::Op(",") if depth == 0 => {
+                operands.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    operands.push(&tokens[start..]);
+    operands
+}
+
+fn is_instruction(name: &str) -> bool {
+    OPCODES.iter().any(|info| !info.undocumented && info.mnemonic == name)
+}
+
+fn is_directive(name: &str) -> bool {
+    matches!(
+        name,
+        "ORG" | "EQU" | "SET" | "DB" | "DW" | "DS" | "END" | "TITLE" | "PAGE" | "EJECT" |
+        "MACRO" | "LOCAL" | "EXITM" | "ENDM" | "REPT" | "IRP" | "IRPC" |
+        "IF" | "ELSE" | "ENDIF" | "INCLUDE" | "MACLIB"
+    )
+}
+
+/// Splits an identifier, upper-cased, off the front of `text`.
+fn identifier(text: &str) -> Option<(String, &str)> {
+    let text = text.trim_start();
+
+    if !text.starts_with(is_symbol_start) {
+        return None;
+    }
+
+    let end = text.find(|c| !is_symbol_char(c)).unwrap_or(text.len());
+    Some((text[..end].to_ascii_uppercase(), &text[end..]))
+}
+
+/// Removes a trailing comment, ignoring semicolons in strings.
+fn uncomment(text: &str) -> &str {
+    let mut quote = None;
+
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &text[..i],
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            _ => {}
+        }
+    }
+
+    text
+}
+
+/// Splits macro arguments at top level commas. Angle brackets group an
+/// argument containing commas and are removed.
+fn arguments(text: &str) -> Vec<String> {
+    let text = uncomment(text).trim();
+    if text.is_empty() {
+        return Vec::new();
+    }
+
+    let mut arguments = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    let mut bracketed = false;
+    let mut depth = 0;
+
+    for c in text.chars() {
+        match (quote, c) {
+            (Some(q), _) => {
+                if q == c {
+                    quote = None;
+                }
+                current.push(c);
+            }
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                current.push(c);
+            }
+            (None, '<') if !bracketed => bracketed = true,
+            (None, '>') if bracketed => bracketed = false,
+            (None, ',') if !bracketed && depth == 0 => {
+                arguments.push(current.trim().to_owned());
+                current.clear();
+            }
+            _ => {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                current.push(c);
+            }
+        }
+    }
+
+    arguments.push(current.trim().to_owned());
+    arguments
+}
+
+/// Replaces the identifiers in `text` found in `names`. `&` joins a
+/// parameter to adjacent text and also marks a substitution within a string.
+fn substitute(text: &str, names: &HashMap<String, String>) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    let mut quote = None;
+
+    while let Some(c) = rest.chars().next() {
+        let ampersand = c == '&';
+        let word = if ampersand { &rest[1..] } else { rest };
+
+        if (quote.is_none() || ampersand) && word.starts_with(is_symbol_start) {
+            let end = word.find(|c| !is_symbol_char(c)).unwrap_or(word.len());
+
+            match names.get(&word[..end].to_ascii_uppercase()) {
+                Some(value) => out.push_str(value),
+                None if quote.is_some() => out.push_str(&rest[..rest.len() - word.len() + end]),
+                None => out.push_str(&word[..end]),
+            }
+
+            rest = &word[end..];
+            continue;
+        }
+
+        match (quote, c) {
+            (None, '&') => {}
+            (None, '0'..='9') => {
+                let end = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+                out.push_str(&rest[..end]);
+                rest = &rest[end..];
+                continue;
+            }
+            (None, ';') => {
+                out.push_str(rest);
+                break;
+            }
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                out.push(c);
+            }
+            (Some(q), _) if q == c => {
+                quote = None;
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+
+        rest = &rest[c.len_utf8()..];
+    }
+
+    out
+}
+
+struct Assembler<'r> {
+    include: &'r mut dyn FnMut(&str) -> io::Result<String>,
+    includes: HashMap<String, Rc<[Line]>>,
+    macros: HashMap<String, Rc<Macro>>,
+    /// Numbers the `LOCAL` symbols of each expansion.
+    locals: usize,
+    depth: usize,
+    pass: u8,
+    pc: u16,
+    /// The value of `$`, the address of the current statement.
+    location: u16,
+    scope: String,
+    symbols: BTreeMap<String, u16>,
+    variables: BTreeSet<String>,
+    image: Vec<u8>,
+    extent: Option<(usize, usize)>,
+    start: Option<u16>,
+}
+
+impl<'r> Assembler<'r> {
+    fn new(include: &'r mut dyn FnMut(&str) -> io::Result<String>) -> Self {
+        Assembler {
+            include,
+            includes: HashMap::new(),
+            macros: HashMap::new(),
+            locals: 0,
+            depth: 0,
+            pass: 0,
+            pc: 0,
+            location: 0,
+            scope: String::new(),
+            symbols: BTreeMap::new(),
+            variables: BTreeSet::new(),
+            image: Vec::new(),
+            extent: None,
+            start: None,
+        }
+    }
+
+    fn begin(&mut self, pass: u8) {
+        self.pass = pass;
+        self.pc = 0;
+        self.scope.clear();
+        self.image = vec![0; 0x10000];
+        self.extent = None;
+        self.macros.clear();
+        self.locals = 0;
+    }
+
+    fn finish(self) -> Assembly {
+        let (low, high) = self.extent.unwrap_or((0, 0));
+
+        Assembly {
+            origin: low as u16,
+            bytes: self.image[low..high].to_vec(),
+            symbols: self.symbols,
+            start: self.start,
+        }
+    }
+
+    /// Splits a line into its label, operation and the text that follows.
+    fn keyword<'t>(&self, text: &'t str) -> (Option<String>, Option<String>, &'t str) {
+        let (first, rest) = match identifier(text) {
+            Some(found) => found,
+            None => return (None, None, text),
+        };
+
+        if let Some(rest) = rest.trim_start().strip_prefix(':') {
+            match identifier(rest) {
+                Some((operation, rest)) => (Some(first), Some(operation), rest),
+                None => (Some(first), None, rest),
+            }
+        } else if self.is_operation(&first) {
+            (None, Some(first), rest)
+        } else {
+            match identifier(rest) {
+                Some((operation, rest)) => (Some(first), Some(operation), rest),
+                None => (Some(first), None, rest),
+            }
+        }
+    }
+
+    fn block(&mut self, lines: &[Line]) -> Result<Flow, Error> {
+        let mut conditions: Vec<Condition> = Vec::new();
+        let mut index = 0;
+
+        while index < lines.len() {
+            let line = &lines[index];
+            index += 1;
+            self.location = self.pc;
+
+            let (label, operation, rest) = self.keyword(&line.text);
+            let active = conditions.last().is_none_or(|condition| condition.active);
+
+            match operation.as_deref() {
+                Some("IF") => {
+                    let value = if active {
+                        self.expression(rest).map_err(|kind| line.error(kind))? != 0
+                    } else {
+                        false
+                    };
+
+                    conditions.push(Condition {
+                        enclosing: active,
+                        active: value,
+                        seen_else: false,
+                    });
+                }
+                Some("ELSE") => match conditions.last_mut() {
+                    Some(condition) if !condition.seen_else => {
+                        condition.active = condition.enclosing && !condition.active;
+                        condition.seen_else = true;
+                    }
+                    _ => return Err(line.syntax("ELSE without IF")),
+                },
+                Some("ENDIF") => {
+                    if conditions.pop().is_none() {
+                        return Err(line.syntax("ENDIF without IF"));
+                    }
+                }
+                _ if !active => {}
+                Some("MACRO") => {
+                    let name = label.ok_or_else(|| line.syntax("missing macro name"))?;
+                    let parameters = arguments(rest).into_iter()
+                        .map(|parameter| parameter.to_ascii_uppercase())
+                        .collect();
+                    let body = self.body(lines, &mut index).map_err(|kind| line.error(kind))?;
+
+                    self.macros.insert(name, Rc::new(Macro { parameters, body }));
+                }
+                Some(operation @ ("REPT" | "IRP" | "IRPC")) => {
+                    self.label(label.as_deref()).map_err(|kind| line.error(kind))?;
+                    let body = self.body(lines, &mut index).map_err(|kind| line.error(kind))?;
+
+                    if let Flow::End = self.repeat(line, operation, rest, &body)? {
+                        return Ok(Flow::End);
+                    }
+                }
+                Some("ENDM") => return Err(line.syntax("ENDM without MACRO")),
+                Some("EXITM") => return Ok(Flow::Exit),
+                Some(operation @ ("INCLUDE" | "MACLIB")) => {
+                    let name = uncomment(rest).trim().trim_matches(|c| c == '\'' || c == '"');
+                    let name = match operation {
+                        "MACLIB" => format!("{}.LIB", name),
+                        _ => name.to_owned(),
+                    };
+                    let included = self.included(&name).map_err(|kind| line.error(kind))?;
+
+                    if let Flow::End = self.nested(line, &included)? {
+                        return Ok(Flow::End);
+                    }
+                }
+                Some(name) if self.macros.contains_key(name) => {
+                    let definition = self.macros[name].clone();
+                    self.label(label.as_deref()).map_err(|kind| line.error(kind))?;
+
+                    let mut names = definition.parameters.iter()
+                        .cloned()
+                        .zip(arguments(rest).into_iter().chain(std::iter::repeat(String::new())))
+                        .collect::<HashMap<_, _>>();
+                    let expansion = self.expand(line, &definition.body, &mut names);
+
+                    if let Flow::End = self.nested(line, &expansion)? {
+                        return Ok(Flow::End);
+                    }
+                }
+                _ => {
+                    let tokens = tokenize(&line.text).map_err(|kind| line.error(kind))?;
+
+                    if !self.statement(&tokens).map_err(|kind| line.error(kind))? {
+                        return Ok(Flow::End);
+                    }
+                }
+            }
+        }
+
+        if !conditions.is_empty() {
+            let last = lines.last().unwrap();
+            return Err(last.syntax("missing ENDIF"));
+        }
+
+        Ok(Flow::Next)
+    }
+
+    /// Collects the lines up to the `ENDM` closing a definition.
+    fn body(&self, lines: &[Line], index: &mut usize) -> Result<Rc<[Line]>, ErrorKind> {
+        let start = *index;
+        let mut depth = 0;
+
+        while let Some(line) = lines.get(*index) {
+            *index += 1;
+
+            match self.keyword(&line.text).1.as_deref() {
+                Some("MACRO" | "REPT" | "IRP" | "IRPC") => depth += 1,
+                Some("ENDM") if depth == 0 => return Ok(lines[start..*index - 1].into()),
+                Some("ENDM") => depth -= 1,
+                _ => {}
+            }
+        }
+
+        syntax("missing ENDM")
+    }
+
+    fn repeat(&mut self, line: &Line, operation: &str, rest: &str, body: &[Line])
+        -> Result<Flow, Error>
+    {
+        let substitutions = match operation {
+            "REPT" => {
+                let count = self.expression(rest).map_err(|kind| line.error(kind))?;
+                (0..count).map(|_| None).collect::<Vec<_>>()
+            }
+            _ => {
+                let mut arguments = arguments(rest).into_iter();
+                let parameter = match arguments.next() {
+                    Some(parameter) if !parameter.is_empty() => parameter.to_ascii_uppercase(),
+                    _ => return Err(line.error(ErrorKind::InvalidOperands(operation.to_owned()))),
+                };
+                let list = arguments.next().unwrap_or_default();
+                let values = match operation {
+                    "IRP" => self::arguments(&list),
+                    _ => list.chars().map(String::from).collect(),
+                };
+
+                values.into_iter().map(|value| Some((parameter.clone(), value))).collect()
+            }
+        };
+
+        for substitution in substitutions {
+            let mut names = substitution.into_iter().collect::<HashMap<_, _>>();
+            let expansion = self.expand(line, body, &mut names);
+
+            match self.nested(line, &expansion)? {
+                Flow::Next => {}
+                Flow::Exit => break,
+                Flow::End => return Ok(Flow::End),
+            }
+        }
+
+        Ok(Flow::Next)
+    }
+
+    /// Instantiates `body`, giving each of its `LOCAL` symbols a unique name.
+    fn expand(&mut self, line: &Line, body: &[Line], names: &mut HashMap<String, String>)
+        -> Vec<Line>
+    {
+        let mut expansion = Vec::new();
+
+        for body_line in body {
+            match self.keyword(&body_line.text) {
+                (None, Some(operation), rest) if operation == "LOCAL" => {
+                    for local in arguments(rest) {
+                        self.locals += 1;
+                        names.insert(local.to_ascii_uppercase(), format!("??{:04}", self.locals));
+                    }
+                }
+                _ => expansion.push(line.expanded(substitute(&body_line.text, names))),
+            }
+        }
+
+        expansion
+    }
+
+    fn nested(&mut self, line: &Line, lines: &[Line]) -> Result<Flow, Error> {
+        if self.depth == MAX_DEPTH {
+            return Err(line.syntax("macros or includes nested too deeply"));
+        }
+
+        self.depth += 1;
+        let flow = self.block(lines);
+        self.depth -= 1;
+
+        flow
+    }
+
+    fn included(&mut self, name: &str) -> Result<Rc<[Line]>, ErrorKind> {
+        if let Some(lines) = self.includes.get(name) {
+            return Ok(lines.clone());
+        }
+
+        let source = (self.include)(name)
+            .map_err(|error| ErrorKind::Include(format!("{}: {}", name, error)))?;
+        let lines = split(&source, Some(name.into()));
+
+        self.includes.insert(name.to_owned(), lines.clone());
+        Ok(lines)
+    }
+
+    fn expression(&self, text: &str) -> Result<i32, ErrorKind> {
+        self.value(&tokenize(text)?)
+    }
+
+    fn label(&mut self, name: Option<&str>) -> Result<(), ErrorKind> {
+        self.location = self.pc;
+
+        match name {
+            Some(name) => {
+                if !name.starts_with('.') {
+                    self.scope = name.to_owned();
+                }
+
+                let name = self.qualify(name);
+                self.define(name, self.pc)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Processes one line, returning `false` at `END`.
+    fn statement(&mut self, tokens: &[Token]) -> Result<bool, ErrorKind> {
+        self.location = self.pc;
+
+        let (label, rest) = match tokens {
+            [Token::Ident(name), Token::Op(":"), rest @ ..] => (Some(name), rest),
+            [Token::Ident(name), Token::Ident(op), ..] if op == "EQU" || op == "SET" => {
+                (Some(name), &tokens[1..])
+            }
+            [Token::Ident(name), rest @ ..] if !self.is_operation(name) => (Some(name), rest),
+            _ => (None, tokens),
+        };
+
+        let (operation, arguments) = match rest {
+            [] => (None, rest),
+            [Token::Ident(operation), arguments @ ..] => (Some(&operation[..]), arguments),
+            _ => return syntax("expected an instruction or directive"),
+        };
+
+        match (label, operation) {
+            (Some(name), Some("EQU")) => {
+                let name = self.qualify(name);
+                self.equate(name, arguments, false)?;
+            }
+            (Some(name), Some("SET")) => {
+                let name = self.qualify(name);
+                self.equate(name, arguments, true)?;
+            }
+            (None, Some("EQU" | "SET")) => return syntax("missing symbol name"),
+            (label, _) => {
+                self.label(label.map(|name| &name[..]))?;
+
+                if let Some(operation) = operation {
+                    return self.operation(operation, arguments);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn is_operation(&self, name: &str) -> bool {
+        is_instruction(name) || is_directive(name) || self.macros.contains_key(name)
+    }
+
+    fn operation(&mut self, name: &str, arguments: &[Token]) -> Result<bool, ErrorKind> {
+        match name {
+            // These move the labels that follow, so unlike data they can't
+            // wait for the second pass to refer to later symbols.
+            "ORG" => self.pc = self.word(self.value(arguments)?)?,
+            "DS" => self.pc = self.pc.wrapping_add(self.word(self.value(arguments)?)?),
+            // Like instructions, data is only sized on the first pass so that
+            // it may refer to later labels.
+            "DB" => {
+                for item in operands(arguments) {
+                    match item {
+                        [Token::Str(string)] if string.len() != 1 => {
+                            if self.pass == 1 {
+                                self.pc = self.pc.wrapping_add(string.len() as u16);
+                                continue;
+                            }
+
+                            for &byte in string {
+                                self.emit(byte);
+                            }
+                        }
+                        _ if self.pass == 1 => self.pc = self.pc.wrapping_add(1),
+                        _ => {
+                            let value = self.value(item)?;
+                            self.emit(self.byte(value)?);
+                        }
+                    }
+                }
+            }
+            "DW" => {
+                for item in operands(arguments) {
+                    if self.pass == 1 {
+                        self.pc = self.pc.wrapping_add(2);
+                        continue;
+                    }
+
+                    let value = self.value(item)?;
+                    self.emit_word(self.word(value)?);
+                }
+            }
+            "TITLE" | "PAGE" | "EJECT" => {}
+            "END" => {
+                if !arguments.is_empty() {
+                    let start = self.value(arguments)?;
+                    self.start = Some(self.word(start)?);
+                }
+
+                return Ok(false);
+            }
+            _ => self.instruction(name, arguments)?,
+        }
+
+        Ok(true)
+    }
+
+    fn instruction(&mut self, name: &str, arguments: &[Token]) -> Result<(), ErrorKind> {
+        let mut candidates = OPCODES.iter()
+            .enumerate()
+            .filter(|(_, info)| !info.undocumented && info.mnemonic == name)
+            .peekable();
+
+        let size = match candidates.peek() {
+            Some((_, info)) => info.size,
+            None => return Err(ErrorKind::UnknownInstruction(name.to_owned())),
+        };
+
+        // Every encoding of a mnemonic is the same size, so the first pass
+        // only needs to advance the location counter.
+        if self.pass == 1 {
+            self.pc = self.pc.wrapping_add(size as u16);
+            return Ok(());
+        }
+
+        let arguments = operands(arguments);
+
+        for (opcode, info) in candidates {
+            if info.operands.len() != arguments.len() {
+                continue;
+            }
+
+            let mut immediate = None;
+            let mut matched = true;
+
+            for (operand, tokens) in info.operands.iter().zip(&arguments) {
+                matched &= match *operand {
+                    Operand::Reg(reg) => register(tokens) == Some(reg),
+                    Operand::Pair(pair) => self::pair(tokens) == Some(pair),
+                    Operand::Vector(n) => self.value(tokens)? == n as i32,
+                    Operand::D8 | Operand::Port | Operand::D16 | Operand::Addr => {
+                        immediate = Some((*operand, *tokens));
+                        true
+                    }
+                };
+            }
+
+            if !matched {
+                continue;
+            }
+
+            self.emit(opcode as u8);
+
+            match immediate {
+                Some((Operand::D8 | Operand::Port, tokens)) => {
+                    let value = self.value(tokens)?;
+                    self.emit(self.byte(value)?);
+                }
+                Some((_, tokens)) => {
+                    let value = self.value(tokens)?;
+                    self.emit_word(self.word(value)?);
+                }
+                None => {}
+            }
+
+            return Ok(());
+        }
+
+        Err(ErrorKind::InvalidOperands(name.to_owned()))
+    }
+
+    fn equate(&mut self, name: String, arguments: &[Token], variable: bool)
+        -> Result<(), ErrorKind>
+    {
+        let value = match self.resolve(arguments)? {
+            Some(value) => self.word(value)?,
+            None => return Ok(()),
+        };
+
+        if variable {
+            if self.symbols.contains_key(&name) && !self.variables.contains(&name) {
+                return Err(ErrorKind::DuplicateSymbol(name));
+            }
+
+            self.variables.insert(name.clone());
+            self.symbols.insert(name, value);
+            Ok(())
+        } else {
+            self.define(name, value)
+        }
+    }
+
+    fn define(&mut self, name: String, value: u16) -> Result<(), ErrorKind> {
+        if self.pass == 1 && self.symbols.contains_key(&name) {
+            return Err(ErrorKind::DuplicateSymbol(name));
+        }
+
+        self.symbols.insert(name, value);
+        Ok(())
+    }
+
+    fn qualify(&self, name: &str) -> String {
+        if name.starts_with('.') {
+            format!("{}{}", self.scope, name)
+        } else {
+            name.to_owned()
+        }
+    }
+
+    fn emit(&mut self, byte: u8) {
+        let address = self.pc as usize;
+        self.image[address] = byte;
+        self.pc = self.pc.wrapping_add(1);
+
+        self.extent = Some(match self.extent {
+            Some((low, high)) => (low.min(address), high.max(address + 1)),
+            None => (address, address + 1),
+        });
+    }
+
+    fn emit_word(&mut self, word: u16) {
+        self.emit(word as u8);
+        self.emit((word >> 8) as u8);
+    }
+
+    /// Bytes may also be given as 16-bit values whose high byte is all ones,
+    /// such as `NOT 0`.
+    fn byte(&self, value: i32) -> Result<u8, ErrorKind> {
+        if (-0x100..=0xff).contains(&value) || (0xff00..=0xffff).contains(&value) {
+            Ok(value as u8)
+        } else {
+            Err(ErrorKind::OutOfRange(value))
+        }
+    }
+
+    fn word(&self, value: i32) -> Result<u16, ErrorKind> {
+        if (-0x10000..=0xffff).contains(&value) {
+            Ok(value as u16)
+        } else {
+            Err(ErrorKind::OutOfRange(value))
+        }
+    }
+
+    /// Evaluates `tokens`, or returns `None` for a forward reference on the
+    /// first pass, which is resolved on the second.
+    fn resolve(&self, tokens: &[Token]) -> Result<Option<i32>, ErrorKind> {
+        match self.value(tokens) {
+            Ok(value) => Ok(Some(value)),
+            Err(ErrorKind::UndefinedSymbol(_)) if self.pass == 1 => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn value(&self, tokens: &[Token]) -> Result<i32, ErrorKind> {
+        if tokens.is_empty() {
+            return syntax("expected an expression");
+        }
+
+        let mut expression = Expression { tokens, position: 0, assembler: self };
+        let value = expression.or()?;
+
+        match expression.tokens.get(expression.position) {
+            None => Ok(value),
+            Some(_) => syntax("unexpected token in expression"),
+        }
+    }
+}
+
+/// A recursive descent evaluator using Intel operator precedence.
+struct Expression<'a, 'r> {
+    tokens: &'a [Token],
+    position: usize,
+    assembler: &'a Assembler<'r>,
+}
+
+impl<'a, 'r> Expression<'a, 'r> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn accept(&mut self, operators: &[&'static str]) -> Option<&'static str> {
+        let found = match self.peek()? {
+            Token::Op(op) => operators.iter().find(|&candidate| candidate == op),
+            Token::Ident(name) => operators.iter().find(|&candidate| candidate == name),
+            _ => None,
+        };
+
+        let found = *found?;
+        self.position += 1;
+        Some(found)
+    }
+
+    fn or(&mut self) -> Result<i32, ErrorKind> {
+        let mut value = self.and()?;
+
+        while let Some(op) = self.accept(&["OR", "XOR"]) {
+            let rhs = self.and()?;
+            value = if op == "OR" { value | rhs } else { value ^ rhs };
+        }
+
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<i32, ErrorKind> {
+        let mut value = self.not()?;
+
+        while self.accept(&["AND"]).is_some() {
+            value &= self.not()?;
+        }
+
+        Ok(value)
+    }
+
+    fn not(&mut self) -> Result<i32, ErrorKind> {
+        if self.accept(&["NOT"]).is_some() {
+            return Ok(!self.not()? & 0xffff);
+        }
+
+        self.relation()
+    }
+
+    fn relation(&mut self) -> Result<i32, ErrorKind> {
+        const RELATIONS: [&str; 12] = [
+            "EQ", "NE", "LT", "LE", "GT", "GE", "=", "<>", "<", "<=", ">", ">=",
+        ];
+
+        let lhs = self.sum()?;
+
+        let op = match self.accept(&RELATIONS) {
+            Some(op) => op,
+            None => return Ok(lhs),
+        };
+
+        let (lhs, rhs) = (lhs & 0xffff, self.sum()? & 0xffff);
+        let result = match op {
+            "EQ" | "=" => lhs == rhs,
+            "NE" | "<>" => lhs != rhs,
+            "LT" | "<" => lhs < rhs,
+            "LE" | "<=" => lhs <= rhs,
+            "GT" | ">" => lhs > rhs,
+            _ => lhs >= rhs,
+        };
+
+        Ok(if result { 0xffff } else { 0 })
+    }
+
+    fn sum(&mut self) -> Result<i32, ErrorKind> {
+        let mut value = self.product()?;
+
+        while let Some(op) = self.accept(&["+", "-"]) {
+            let rhs = self.product()?;
+            value = if op == "+" { value.wrapping_add(rhs) } else { value.wrapping_sub(rhs) };
+        }
+
+        Ok(value)
+    }
+
+    fn product(&mut self) -> Result<i32, ErrorKind> {
+        let mut value = self.unary()?;
+
+        while let Some(op) = self.accept(&["*", "/", "MOD", "SHL", "SHR"]) {
+            let rhs = self.unary()?;
+
+            value = match op {
+                "*" => value.wrapping_mul(rhs),
+                "SHL" => (value & 0xffff).checked_shl(rhs as u32).unwrap_or(0),
+                "SHR" => (value & 0xffff).checked_shr(rhs as u32).unwrap_or(0),
+                _ if rhs == 0 => return syntax("division by zero"),
+                "/" => value.wrapping_div(rhs),
+                _ => value.wrapping_rem(rhs),
+            };
+        }
+
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i32, ErrorKind> {
+        // `NUL` tests whether the rest of the operand, usually a macro
+        // argument, is empty.
+        if self.accept(&["NUL"]).is_some() {
+            let empty = self.position == self.tokens.len();
+            self.position = self.tokens.len();
+            return Ok(if empty { 0xffff } else { 0 });
+        }
+
+        match self.accept(&["-", "+", "HIGH", "LOW"]) {
+            Some("-") => Ok(self.unary()?.wrapping_neg()),
+            Some("+") => self.unary(),
+            Some("HIGH") => Ok((self.unary()? >> 8) & 0xff),
+            Some("LOW") => Ok(self.unary()? & 0xff),
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<i32, ErrorKind> {
+        let token = match self.peek() {
+            Some(token) => token,
+            None => return syntax("expected an expression"),
+        };
+        self.position += 1;
+
+        match *token {
+            Token::Number(value) => Ok(value),
+            Token::Dollar => Ok(self.assembler.location as i32),
+            Token::Str(ref string) => match string[..] {
+                [c] => Ok(c as i32),
+                [high, low] => Ok((high as i32) << 8 | low as i32),
+                _ => syntax("strings in expressions must be one or two characters"),
+            },
+            Token::Ident(ref name) => {
+                let name = self.assembler.qualify(name);
+
+                self.assembler.symbols
+                    .get(&name)
+                    .map(|&value| value as i32)
+                    .ok_or(ErrorKind::UndefinedSymbol(name))
+            }
+            Token::Op("(") => {
+                let value = self.or()?;
+
+                match self.accept(&[")"]) {
+                    Some(_) => Ok(value),
+                    None => syntax("expected ')'"),
+                }
+            }
+            Token::Op(_) => syntax("expected an expression"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        assemble(source).unwrap().bytes
+    }
+
+    #[test]
+    fn encodes_every_documented_opcode() {
+        use crate::disasm::Instruction;
+
+        for (opcode, info) in OPCODES.iter().enumerate() {
+            if info.undocumented {
+                continue;
+            }
+
+            let instruction = Instruction::decode(&[opcode as u8, 0x34, 0x12], 0).unwrap();
+            assert_eq!(bytes(&instruction.to_string()), instruction.bytes(), "{}", instruction);
+        }
+    }
+
+    #[test]
+    fn labels_and_directives() {
+        let assembly = assemble("
+            ORG 100H
+    COUNT   EQU 3
+    START:  MVI B,COUNT         ; forward and backward references
+    .loop:  DCR B
+            JNZ .loop
+            JMP DONE
+    TABLE:  DB 'Hi',0,-1
+            DW START,$
+            DS 2
+    DONE    HLT
+            END START
+        ").unwrap();
+
+        assert_eq!(assembly.origin, 0x100);
+        assert_eq!(assembly.start, Some(0x100));
+        assert_eq!(assembly.symbols["START.LOOP"], 0x102);
+        assert_eq!(assembly.symbols["DONE"], 0x113);
+        assert_eq!(assembly.bytes, [
+            0x06, 0x03,
+            0x05,
+            0xc2, 0x02, 0x01,
+            0xc3, 0x13, 0x01,
+            0x48, 0x69, 0x00, 0xff,
+            0x00, 0x01, 0x0d, 0x01,
+            0x00, 0x00,
+            0x76,
+        ]);
+    }
+
+    #[test]
+    fn forward_references_in_data() {
+        let assembly = assemble("ORG 100H\nTABLE: DW A1,A2\nA1: NOP\nA2: RET").unwrap();
+        assert_eq!(assembly.bytes, [0x04, 0x01, 0x05, 0x01, 0x00, 0xc9]);
+
+        assert_eq!(bytes("DW LATER\nLATER: NOP"), [0x02, 0x00, 0x00]);
+        assert_eq!(bytes("DB LOW LATER, 'ab', HIGH LATER\nLATER: NOP"),
+                   [0x04, b'a', b'b', 0x00, 0x00]);
+
+        let assembly = assemble("LEN EQU 2\nJMP DONE\nDS LEN\nDONE: HLT").unwrap();
+        assert_eq!(assembly.symbols["DONE"], 5);
+        assert_eq!(assembly.bytes, [0xc3, 0x05, 0x00, 0x00, 0x00, 0x76]);
+
+        // Labels after a DS or ORG would move between passes, so those can't
+        // refer ahead.
+        let error = |source| assemble(source).unwrap_err().kind;
+        assert_eq!(error("JMP DONE\nDS LEN\nLEN EQU 2\nDONE: HLT"),
+                   ErrorKind::UndefinedSymbol("LEN".into()));
+        assert_eq!(error("ORG X\nJMP L\nL: NOP\nX EQU 100H"),
+                   ErrorKind::UndefinedSymbol("X".into()));
+        assert_eq!(error("DW NOWHERE"), ErrorKind::UndefinedSymbol("NOWHERE".into()));
+    }
+
+    #[test]
+    fn expressions() {
+        assert_eq!(bytes("DB 1+2*3, (1+2)*3, 10 MOD 3, 1 SHL 4, 80H SHR 7"), [7, 9, 1, 16, 1]);
+        assert_eq!(bytes("DB HIGH 1234H, LOW 1234H, NOT 0 AND 0FH, 5 XOR 3 OR 8"),
+                   [0x12, 0x34, 0x0f, 0x0e]);
+        assert_eq!(bytes("DB 1 EQ 1, 2 LT 1, 'A'+1, 0FFH, 17Q, 101B, 10D"),
+                   [0xff, 0, 0x42, 0xff, 15, 5, 10]);
+        assert_eq!(bytes("LXI H,'AB'"), [0x21, 0x42, 0x41]);
+        assert_eq!(bytes("DB 'It''s'"), b"It's");
+    }
+
+    #[test]
+    fn macros() {
+        let assembly = assemble("
+    ; Waits for a port bit, with a local loop label.
+    WAIT    MACRO PORT,BIT
+            LOCAL AGAIN
+    AGAIN:  IN PORT
+            ANI 1 SHL BIT
+            JZ AGAIN
+            ENDM
+
+    LOAD    MACRO R,VALUE
+            IF NUL VALUE
+            MVI R,0
+            EXITM
+            ENDIF
+            MVI R,VALUE
+            DB 'R&R'
+            ENDM
+
+    START:  WAIT 10H,2
+            WAIT 11H,0
+            LOAD B,<5>
+            LOAD C
+        ").unwrap();
+
+        assert_eq!(assembly.symbols["??0001"], 0);
+        assert_eq!(assembly.symbols["??0002"], 7);
+        assert_eq!(assembly.bytes, [
+            0xdb, 0x10, 0xe6, 0x04, 0xca, 0x00, 0x00,
+            0xdb, 0x11, 0xe6, 0x01, 0xca, 0x07, 0x00,
+            0x06, 0x05, b'R', b'B',
+            0x0e, 0x00,
+        ]);
+    }
+
+    #[test]
+    fn repetition() {
+        assert_eq!(bytes("REPT 3\nNOP\nENDM"), [0, 0, 0]);
+        assert_eq!(bytes("IRP R,<B,C,D>\nINR R\nENDM"), [0x04, 0x0c, 0x14]);
+        assert_eq!(bytes("IRPC C,AB\nDB '&C'\nENDM"), b"AB");
+        assert_eq!(bytes("REPT 4\nIF $ EQ 2\nEXITM\nENDIF\nNOP\nENDM\nHLT"), [0, 0, 0x76]);
+    }
+
+    #[test]
+    fn conditionals() {
+        let source = "
+    DEBUG   EQU 1
+            IF DEBUG
+            IF 0
+            DB 1
+            ELSE
+            DB 2
+            ENDIF
+            ELSE
+            DB 3
+            IF 1
+            DB 4
+            ENDIF
+            ENDIF
+        ";
+
+        assert_eq!(bytes(source), [2]);
+        assert_eq!(bytes(&source.replace("EQU 1", "EQU 0")), [3, 4]);
+
+        let error = |source| assemble(source).unwrap_err().kind;
+        assert_eq!(error("IF 1\nNOP"), ErrorKind::Syntax("missing ENDIF".into()));
+        assert_eq!(error("ELSE"), ErrorKind::Syntax("ELSE without IF".into()));
+        assert_eq!(error("IF LATER\nENDIF\nLATER: NOP"),
+                   ErrorKind::UndefinedSymbol("LATER".into()));
+        assert_eq!(error("M MACRO\nNOP"), ErrorKind::Syntax("missing ENDM".into()));
+    }
+
+    #[test]
+    fn includes() {
+        let files = HashMap::from([
+            ("CONST.LIB", "BDOS EQU 5\nPRINT MACRO\nCALL BDOS\nENDM"),
+            ("bad.asm", "NOP\nJMP NOWHERE"),
+        ]);
+        let include = |name: &str| {
+            files.get(name)
+                .map(|source| source.to_string())
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+        };
+
+        let assembly = assemble_with("MACLIB CONST\nPRINT", include).unwrap();
+        assert_eq!(assembly.bytes, [0xcd, 0x05, 0x00]);
+
+        let error = assemble_with("NOP\nINCLUDE 'bad.asm'", include).unwrap_err();
+        assert_eq!(error.to_string(), "bad.asm:2: undefined symbol NOWHERE");
+
+        let error = assemble("INCLUDE missing.asm").unwrap_err();
+        assert_eq!(error.line, 1);
+        assert!(matches!(error.kind, ErrorKind::Include(_)));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source| assemble(source).unwrap_err();
+
+        assert_eq!(error("NOP\nJMP NOWHERE"),
+                   Error {
+                       file: None,
+                       line: 2,
+                       kind: ErrorKind::UndefinedSymbol("NOWHERE".into()),
+                   });
+        assert_eq!(error("A: NOP\nA: NOP").kind, ErrorKind::DuplicateSymbol("A".into()));
+        assert_eq!(error("FOO A").kind, ErrorKind::UnknownInstruction("A".into()));
+        assert_eq!(error("MOV A").kind, ErrorKind::InvalidOperands("MOV".into()));
+        assert_eq!(error("MOV M,M").kind, ErrorKind::InvalidOperands("MOV".into()));
+        assert_eq!(error("MVI A,100H").kind, ErrorKind::OutOfRange(0x100));
+        assert_eq!(error("DB 'oops").kind, ErrorKind::Syntax("unterminated string".into()));
+    }
+}
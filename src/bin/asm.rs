@@ -0,0 +1,82 @@
+use clap::{App, Arg};
+use i8080::{asm, ihex, srec, Image};
+
+use std::fs;
+use std::path::Path;
+use std::process;
+
+fn main() {
+    let matches = App::new("asm")
+        .about("Assembles Intel 8080 source")
+        .arg(Arg::new("output")
+            .short('o')
+            .long("output")
+            .takes_value(true)
+            .help("Output file; the extension selects the format unless --format is given"))
+        .arg(Arg::new("format")
+            .short('f')
+            .long("format")
+            .takes_value(true)
+            .possible_values(["bin", "hex", "srec"])
+            .help("Raw binary, Intel HEX or Motorola S-records"))
+        .arg(Arg::new("symbols")
+            .short('s')
+            .long("symbols")
+            .help("Print the symbol table"))
+        .arg(Arg::new("SOURCE")
+            .required(true)
+            .help("Source file"))
+        .get_matches();
+
+    let source_path = Path::new(matches.value_of("SOURCE").unwrap());
+    let extension = matches.value_of("output")
+        .and_then(|path| Path::new(path).extension())
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    let format = match (matches.value_of("format"), extension.as_deref()) {
+        (Some(format), _) => format,
+        (None, Some("hex" | "ihx")) => "hex",
+        (None, Some("srec" | "s19" | "mot")) => "srec",
+        (None, _) => "bin",
+    };
+    let output = matches.value_of("output")
+        .map(Into::into)
+        .unwrap_or_else(|| source_path.with_extension(format));
+
+    let source = fs::read_to_string(source_path).unwrap_or_else(|error| {
+        eprintln!("{}: {}", source_path.display(), error);
+        process::exit(1);
+    });
+
+    // Included files are found next to the source. CP/M sources name them in
+    // upper case, so fall back to the lower case name.
+    let directory = source_path.parent().unwrap_or_else(|| Path::new(""));
+    let include = |name: &str| {
+        fs::read_to_string(directory.join(name))
+            .or_else(|_| fs::read_to_string(directory.join(name.to_ascii_lowercase())))
+    };
+
+    let assembly = asm::assemble_with(&source, include).unwrap_or_else(|error| {
+        eprintln!("{}: {}", source_path.display(), error);
+        process::exit(1);
+    });
+
+    let mut image = Image::new(assembly.origin, assembly.bytes);
+    image.start = assembly.start;
+
+    let result = match format {
+        "hex" => fs::write(&output, ihex::encode(&image)),
+        "srec" => fs::write(&output, srec::encode(&image)),
+        _ => fs::write(&output, &image.segments[0].data),
+    };
+
+    if let Err(error) = result {
+        eprintln!("{}: {}", output.display(), error);
+        process::exit(1);
+    }
+
+    if matches.is_present("symbols") {
+        for (name, value) in &assembly.symbols {
+            println!("{:04X}  {}", value, name);
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use clap::{App, Arg};
+use i8080::cpm::Cpm;
+use i8080::Cpu;
+
+use std::fs;
+use std::process;
+
+fn main() {
+    let matches = App::new("cpm")
+        .about("Runs a CP/M 2.2 .COM program")
+        .arg(Arg::new("directory")
+            .short('d')
+            .long("directory")
+            .takes_value(true)
+            .value_name("DIR")
+            .default_value(".")
+            .help("The directory that stands in for every drive"))
+        .arg(Arg::new("PROGRAM")
+            .required(true)
+            .help("The .COM file to run"))
+        .arg(Arg::new("ARGS")
+            .multiple_values(true)
+            .help("The command tail passed to the program"))
+        .get_matches();
+
+    let path = matches.value_of("PROGRAM").unwrap();
+    let program = fs::read(path).unwrap_or_else(|error| {
+        eprintln!("{}: {}", path, error);
+        process::exit(1);
+    });
+    let arguments = matches.values_of("ARGS").map_or(Vec::new(), |values| values.collect());
+
+    let mut cpu = Cpu::new();
+    let mut cpm = Cpm::new(matches.value_of("directory").unwrap());
+    cpm.load(&mut cpu, &program, &arguments.join(" "));
+
+    if let Err(error) = cpm.run(&mut cpu) {
+        eprintln!();
+        eprintln!("{}", error);
+        eprintln!("{:?}", cpu);
+        process::exit(1);
+    }
+}
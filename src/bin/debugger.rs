@@ -0,0 +1,151 @@
+use clap::{App, Arg};
+use i8080::console::{parse_number, Console};
+use i8080::rewind::Rewind;
+use i8080::trace::History;
+use i8080::{gdb, ihex, srec, Cpu, Debugger, Image, Machine, Memory};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+use std::process;
+
+const HELP: &str = "
+in PORT VALUE     set the value read from an input port
+ports             show the last value written to each output port";
+
+/// Instructions kept for `history`.
+const HISTORY_LEN: usize = 256;
+/// Snapshots kept for `back`, and the instructions run between them.
+const REWIND_LEN: usize = 1024;
+const REWIND_INTERVAL: u64 = 1000;
+
+/// Input ports return the value last set with `in`; output writes are kept
+/// for `ports`.
+struct Ports {
+    input: Vec<u8>,
+    output: BTreeMap<u8, u8>,
+}
+
+impl Machine for Ports {
+    fn input(&mut self, port: u8) -> u8 {
+        self.input[port as usize]
+    }
+
+    fn output(&mut self, port: u8, byte: u8) {
+        self.output.insert(port, byte);
+    }
+}
+
+impl Ports {
+    /// The commands `Ports` adds to the console.
+    fn command(&mut self, command: &str, arguments: &[&str]) -> Option<Result<(), String>> {
+        let result = match (command, arguments) {
+            ("help", _) => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            ("in", [port, value]) => parse_number(port).and_then(|port| {
+                self.input[port as u8 as usize] = parse_number(value)? as u8;
+                Ok(())
+            }),
+            ("in", _) => Err(String::from("expected a port and a value")),
+            ("ports", _) => {
+                for (port, value) in &self.output {
+                    println!("{:02X}  {:02X}", port, value);
+                }
+
+                Ok(())
+            }
+            _ => return None,
+        };
+
+        Some(result)
+    }
+}
+
+/// Loads a ROM file, returning its start address, if it has one, and how far
+/// to move the load position for the next raw file. Images carry their own
+/// addresses, so they don't move it.
+fn load(path: &str, origin: u16, memory: &mut Memory) -> Result<(Option<u16>, u16), String> {
+    let lower = path.to_ascii_lowercase();
+    let error = |error: &dyn std::fmt::Display| format!("{}: {}", path, error);
+
+    if lower.ends_with(".hex") || lower.ends_with(".s19") || lower.ends_with(".srec") {
+        let text = fs::read_to_string(path).map_err(|e| error(&e))?;
+        let image = if lower.ends_with(".hex") { ihex::parse(&text) } else { srec::parse(&text) };
+        let image: Image = image.map_err(|e| error(&e))?;
+
+        image.load(memory);
+        Ok((image.start, 0))
+    } else {
+        let bytes = fs::read(path).map_err(|e| error(&e))?;
+        memory.load(&bytes, origin);
+        Ok((None, bytes.len() as u16))
+    }
+}
+
+fn main() {
+    let matches = App::new("debugger")
+        .about("Interactive Intel 8080 debugger")
+        .arg(Arg::new("origin")
+            .short('o')
+            .long("origin")
+            .takes_value(true)
+            .default_value("0")
+            .help("Address of the first byte of raw ROM files, in hex"))
+        .arg(Arg::new("start")
+            .long("start")
+            .takes_value(true)
+            .help("Initial PC, in hex; defaults to the origin"))
+        .arg(Arg::new("gdb")
+            .long("gdb")
+            .takes_value(true)
+            .value_name("PORT")
+            .help("Serve the GDB remote protocol on a local port instead of the prompt"))
+        .arg(Arg::new("ROM")
+            .required(true)
+            .multiple_occurrences(true)
+            .help("ROM files, loaded one after another; .hex and .s19 files carry addresses"))
+        .get_matches();
+
+    let exit = |error: String| -> ! {
+        eprintln!("{}", error);
+        process::exit(1);
+    };
+
+    let origin = parse_number(matches.value_of("origin").unwrap()).unwrap_or_else(|e| exit(e));
+    let mut cpu = Cpu::with_observer(Memory::default(), Debugger::new());
+    cpu.history = Some(History::new(HISTORY_LEN));
+    cpu.observer.rewind = Some(Rewind::new(REWIND_LEN, REWIND_INTERVAL));
+    let mut position = origin;
+    let mut start = None;
+
+    for path in matches.values_of("ROM").unwrap() {
+        let (image_start, size) = load(path, position, &mut cpu.memory).unwrap_or_else(|e| exit(e));
+        start = image_start.or(start);
+        position = position.wrapping_add(size);
+    }
+
+    cpu.pc = match matches.value_of("start") {
+        Some(text) => parse_number(text).unwrap_or_else(|e| exit(e)),
+        None => start.unwrap_or(origin),
+    }.into();
+
+    let mut console = Console::new(cpu, Ports { input: vec![0; 256], output: BTreeMap::new() });
+
+    if let Some(port) = matches.value_of("gdb") {
+        let listener = TcpListener::bind(("127.0.0.1", port.parse().unwrap_or_else(|_| {
+            exit(format!("invalid port: {}", port))
+        }))).unwrap_or_else(|e| exit(e.to_string()));
+
+        println!("Waiting for GDB on {}", listener.local_addr().unwrap());
+        let (stream, _) = listener.accept().unwrap_or_else(|e| exit(e.to_string()));
+        gdb::Stub::new(stream)
+            .serve(&mut console.cpu, &mut console.machine)
+            .unwrap_or_else(|e| exit(e.to_string()));
+        return;
+    }
+
+    console.run(io::stdin().lock(), &mut Ports::command);
+}
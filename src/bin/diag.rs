@@ -1,48 +1,108 @@
 extern crate i8080;
-use i8080::{Cpu, Machine};
-use std::io::Read;
-use std::fs::File;
+use clap::{App, Arg};
+use i8080::trace::Tracer;
+use i8080::cpm::Cpm;
+use i8080::{ihex, Cpu};
+use std::io::{BufWriter, Read, Write};
+use std::fs::{self, File};
+use std::process;
 
-struct Facade;
-
-impl Machine for Facade {
-    fn input(&mut self, _: u8) -> u8  {0}
-    fn output(&mut self, _: u8, _: u8) {}
+fn parse_range(text: &str) -> Option<(u16, u16)> {
+    let (start, end) = text.split_once('-')?;
+    Some((u16::from_str_radix(start, 16).ok()?, u16::from_str_radix(end, 16).ok()?))
 }
 
 fn main() {
+    let matches = App::new("diag")
+        .about("Runs an Intel 8080 CPU diagnostic")
+        .arg(Arg::new("trace")
+            .short('t')
+            .long("trace")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Write an execution trace to FILE"))
+        .arg(Arg::new("range")
+            .long("range")
+            .takes_value(true)
+            .value_name("START-END")
+            .requires("trace")
+            .help("Only trace instructions between these hex addresses"))
+        .arg(Arg::new("PATH")
+            .default_value("./TEST.COM")
+            .help("The diagnostic, as a raw file loaded at 100H or Intel HEX"))
+        .get_matches();
 
-    if !cfg!(feature = "cpudiag") {
-        println!("Needs to be run with the cpudiag flag");
-        ::std::process::exit(1);
-    }
+    let path = matches.value_of("PATH").unwrap();
 
-    let buffer = {
-        let mut buf = Vec::new();
-        let mut file = File::open("./TEST.COM").unwrap();
-        file.read_to_end(&mut buf).unwrap();
-        buf
-    };
+    let mut tracer = matches.value_of("trace").map(|trace| {
+        let file = File::create(trace).unwrap_or_else(|error| {
+            println!("{}: {}", trace, error);
+            process::exit(1);
+        });
+        let tracer = Tracer::new(BufWriter::new(file));
+
+        match matches.value_of("range") {
+            Some(range) => {
+                let (start, end) = parse_range(range).unwrap_or_else(|| {
+                    println!("invalid range: {}", range);
+                    process::exit(1);
+                });
+
+                tracer.range(start..=end)
+            }
+            None => tracer,
+        }
+    });
 
     let mut cpu = Cpu::new();
-    let mut breakpoint = false;
-    let adr = 0x100u16;
-    cpu.load_into_rom(&buffer, 0x100);
-    cpu.pc = adr.into();
+    let mut cpm = Cpm::new(".");
 
+    if path.to_ascii_lowercase().ends_with(".hex") {
+        let image = ihex::parse(&fs::read_to_string(path).unwrap()).unwrap_or_else(|error| {
+            println!("{}: {}", path, error);
+            ::std::process::exit(1);
+        });
 
-    loop {
-        if false {
-            breakpoint = true;
+        cpm.load(&mut cpu, &[], "");
+        cpu.load_image(&image);
+    } else {
+        let buffer = {
+            let mut buf = Vec::new();
+            let mut file = File::open(path).unwrap();
+            file.read_to_end(&mut buf).unwrap();
+            buf
+        };
+
+        cpm.load(&mut cpu, &buffer, "");
+    }
+
+
+    let mut trapped = false;
+    let error = loop {
+        // A step that stops at a BDOS or BIOS trap runs nothing, so only the
+        // `RET` run there by the following step is traced.
+        trapped = !trapped && cpu.traps.contains(&*cpu.pc);
+
+        if let Some(tracer) = tracer.as_mut().filter(|_| !trapped) {
+            tracer.record(&cpu).unwrap();
         }
 
-        if breakpoint {
-            println!("{:?}", cpu);
-            cpu.print_opcode();
-            ::std::io::stdin().read_line(&mut String::new()).unwrap();
+        match cpm.step(&mut cpu) {
+            Ok(true) => {}
+            Ok(false) => {
+                println!();
+                break None;
+            }
+            Err(error) => break Some(error),
         }
+    };
 
-        cpu.emulate(&mut Facade);
+    if let Some(tracer) = tracer {
+        tracer.into_inner().flush().unwrap();
     }
-}
 
+    if let Some(error) = error {
+        println!("{}", error);
+        ::std::process::exit(1);
+    }
+}
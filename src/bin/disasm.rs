@@ -0,0 +1,74 @@
+use clap::{App, Arg};
+use i8080::disasm;
+
+use std::fs;
+use std::process;
+
+fn parse_address(text: &str) -> Result<u16, String> {
+    let text = text.trim();
+    let digits = text.strip_prefix("0x")
+        .or_else(|| text.strip_suffix(|c| c == 'h' || c == 'H'))
+        .unwrap_or(text);
+
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address: {}", text))
+}
+
+fn main() {
+    let matches = App::new("disasm")
+        .about("Disassembles Intel 8080 ROM images")
+        .arg(Arg::new("origin")
+            .short('o')
+            .long("origin")
+            .takes_value(true)
+            .default_value("0")
+            .help("Address of the first byte, in hex"))
+        .arg(Arg::new("entry")
+            .short('e')
+            .long("entry")
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .help("Trace code from this address, in hex, and emit assembler source"))
+        .arg(Arg::new("ROM")
+            .required(true)
+            .multiple_occurrences(true)
+            .help("ROM files, loaded one after another"))
+        .get_matches();
+
+    let origin = parse_address(matches.value_of("origin").unwrap()).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1);
+    });
+
+    let mut image = Vec::new();
+    for path in matches.values_of("ROM").unwrap() {
+        match fs::read(path) {
+            Ok(bytes) => image.extend(bytes),
+            Err(error) => {
+                eprintln!("{}: {}", path, error);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(entries) = matches.values_of("entry") {
+        let entries = entries.map(parse_address)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                process::exit(1);
+            });
+
+        print!("{}", disasm::trace(&image, origin, &entries));
+        return;
+    }
+
+    for instruction in disasm::disassemble(&image, origin) {
+        let bytes = instruction.bytes()
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        println!("{:04X}  {:<8}  {}", instruction.address, bytes, instruction);
+    }
+}
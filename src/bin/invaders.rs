@@ -2,9 +2,12 @@ use kira::{manager::AudioManager, sound::handle::SoundHandle};
 use winit::{dpi::LogicalSize, event::{KeyboardInput, VirtualKeyCode, Event, WindowEvent}, event_loop::{EventLoop, ControlFlow}, window::WindowBuilder};
 use pixels::{Pixels, SurfaceTexture};
 use i8080::*;
+use i8080::state::{MachineState, Reader, StateError, Writer};
+use i8080::rewind::Rewind;
+use i8080::trace::History;
 
 use std::time::Instant;
-use std::{cmp, mem};
+use std::{cmp, fs, mem};
 
 const WIDTH: u32 = 224;
 const HEIGHT: u32 = 256;
@@ -21,6 +24,14 @@ const LEFT: u8 = 0x20;
 const P1_START: u8 = 0x4;
 const P2_START: u8 = 0x2;
 const RIGHT: u8 = 0x40;
+const RST_1: u8 = 0xcf;
+const RST_2: u8 = 0xd7;
+/// Instructions kept to show how the game reached a crash.
+const HISTORY_LEN: usize = 256;
+/// Where F5 quick-saves to and F9 quick-loads from.
+const SAVE_PATH: &str = "invaders.sav";
+/// Frames of gameplay that holding R can rewind through.
+const REWIND_LEN: usize = 600;
 
 struct Sounds {
     #[allow(unused)]
@@ -84,6 +95,8 @@ impl Sound {
 
 pub struct SpaceInvaders {
     cpu: Cpu,
+    /// Set when the game uses a port the cabinet doesn't have.
+    fault: Option<String>,
     first_port: u8,
     interrupt_num: bool,
     last_port_five: u8,
@@ -91,6 +104,8 @@ pub struct SpaceInvaders {
     next_interrupt: i64,
     overnanos: u64,
     previous: Instant,
+    rewind: Rewind,
+    rewinding: bool,
     second_port: u8,
     shift_offset: u16,
     shiftx: u8,
@@ -101,6 +116,10 @@ pub struct SpaceInvaders {
 impl SpaceInvaders {
     pub fn new() -> Self {
         let mut cpu = Cpu::new();
+        cpu.memory.map_rom(0x0000..0x2000)
+                  .map_ram(0x2000..0x4000)
+                  .mirror(0x4000..=0xffff, 0x2000..0x4000);
+        cpu.history = Some(History::new(HISTORY_LEN));
 
         for &(file, position) in &FILE_POSITIONS {
             cpu.load_into_rom(file, position);
@@ -108,12 +127,15 @@ impl SpaceInvaders {
 
         SpaceInvaders {
             cpu: cpu,
+            fault: None,
             shift_offset: 0,
             shiftx: 0,
             shifty: 0,
             first_port: 1,
             second_port: 0,
             previous: Instant::now(),
+            rewind: Rewind::new(REWIND_LEN, 0),
+            rewinding: false,
             next_interrupt: 0,
             interrupt_num: false,
             overnanos: 0,
@@ -133,6 +155,15 @@ impl SpaceInvaders {
         const NANOS_PER_CYCLE: u64 = NANOS_PER_SEC / HERTZ;
         const INTERRUPT_CYCLES: i64 = 2_000 * 8; //2_000_000Hz * 8ms
 
+        if self.rewinding {
+            if let Some((_, state)) = self.rewind.pop() {
+                self.restore(&state).unwrap();
+            }
+
+            self.previous = Instant::now();
+            return;
+        }
+
         let now = Instant::now();
         let duration = now.duration_since(self.previous);
         let nanos_needed = (duration.as_secs() * NANOS_PER_SEC) +
@@ -151,21 +182,39 @@ impl SpaceInvaders {
         while self.next_interrupt < cycles_needed as i64 {
 
             while self.next_interrupt > cycles_passed as i64  {
-                cycles_passed += cpu.emulate(self) as u64;
+                cycles_passed += self.step(&mut cpu);
             }
 
-            self.try_interrupt(&mut cpu);
+            self.request_interrupt(&mut cpu);
             self.next_interrupt += INTERRUPT_CYCLES;
         }
 
         while cycles_needed > cycles_passed  {
-            cycles_passed += cpu.emulate(self) as u64;
+            cycles_passed += self.step(&mut cpu);
         }
 
         self.next_interrupt -= cycles_passed as i64;
         self.overnanos = cycles_passed * NANOS_PER_CYCLE - nanos_needed;
         self.previous = now;
         self.cpu = cpu;
+        self.rewind.push(self.cpu.cycles, self.cpu.save_state(self));
+    }
+
+    fn step(&mut self, cpu: &mut Cpu) -> u64 {
+        let fault = match cpu.emulate(self) {
+            Ok(cycles) => match self.fault.take() {
+                Some(fault) => fault,
+                None => return cycles as u64,
+            },
+            Err(error) => error.to_string(),
+        };
+
+        if let Some(history) = &cpu.history {
+            eprintln!("Last {} instructions:\n{}", history.len(), history);
+        }
+
+        eprintln!("{:?}", cpu);
+        panic!("{}", fault);
     }
 
     fn update(&mut self, frame: &mut [u8]) {
@@ -208,18 +257,43 @@ impl SpaceInvaders {
         // }
     }
 
-    fn try_interrupt(&mut self, cpu: &mut Cpu) {
+    fn request_interrupt(&mut self, cpu: &mut Cpu) {
         self.interrupt_num = !self.interrupt_num;
-        if !cpu.int_enable {
-            return;
-        }
         if self.interrupt_num {
-            cpu.interrupt(8);
+            cpu.request_interrupt(RST_1);
         } else {
-            cpu.interrupt(16);
+            cpu.request_interrupt(RST_2);
+        }
+    }
+
+    fn quick_save(&self) {
+        match fs::write(SAVE_PATH, self.cpu.save_state(self)) {
+            Ok(()) => println!("Saved to {}", SAVE_PATH),
+            Err(error) => eprintln!("{}: {}", SAVE_PATH, error),
         }
     }
 
+    fn quick_load(&mut self) {
+        let state = match fs::read(SAVE_PATH) {
+            Ok(state) => state,
+            Err(error) => return eprintln!("{}: {}", SAVE_PATH, error),
+        };
+
+        match self.restore(&state) {
+            Ok(()) => println!("Loaded {}", SAVE_PATH),
+            Err(error) => eprintln!("{}: {}", SAVE_PATH, error),
+        }
+
+        self.previous = Instant::now();
+    }
+
+    fn restore(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut cpu = mem::replace(&mut self.cpu, Cpu::new());
+        let result = cpu.load_state(self, state);
+        self.cpu = cpu;
+        result
+    }
+
     pub fn handle_event(&mut self, event: KeyboardInput) {
         match event {
             KeyboardInput {
@@ -244,6 +318,9 @@ impl SpaceInvaders {
             VirtualKeyCode::Space | VirtualKeyCode::F => self.first_port |= FIRE,
             VirtualKeyCode::Key1 => self.first_port |= P1_START,
             VirtualKeyCode::Key2 => self.first_port |= P2_START,
+            VirtualKeyCode::F5 => self.quick_save(),
+            VirtualKeyCode::F9 => self.quick_load(),
+            VirtualKeyCode::R => self.rewinding = true,
             _ => {}
         }
     }
@@ -256,6 +333,7 @@ impl SpaceInvaders {
             VirtualKeyCode::Space | VirtualKeyCode::F => self.first_port &= !FIRE,
             VirtualKeyCode::Key1 => self.first_port &= !P1_START,
             VirtualKeyCode::Key2 => self.first_port &= !P2_START,
+            VirtualKeyCode::R => self.rewinding = false,
             _ => {}
         }
     }
@@ -271,7 +349,10 @@ impl Machine for SpaceInvaders {
                 let value = ((self.shifty as u16) << 8) | self.shiftx as u16;
                 (value >> (8 - self.shift_offset)) as u8
             }
-            code => panic!("Unimplemented INPUT PORT {:?}", code),
+            code => {
+                self.fault = Some(format!("Unimplemented INPUT PORT {:?}", code));
+                0
+            }
         }
     }
 
@@ -352,11 +433,38 @@ impl Machine for SpaceInvaders {
             }
             6 => {}
 
-            code => panic!("Unimplemented OUTPUT PORT {:?}", code),
+            code => self.fault = Some(format!("Unimplemented OUTPUT PORT {:?}", code)),
         }
     }
 }
 
+impl MachineState for SpaceInvaders {
+    fn save_state(&self, writer: &mut Writer) {
+        writer.u8(self.first_port);
+        writer.u8(self.second_port);
+        writer.u8(self.last_port_three);
+        writer.u8(self.last_port_five);
+        writer.u8(self.shiftx);
+        writer.u8(self.shifty);
+        writer.u16(self.shift_offset);
+        writer.bool(self.interrupt_num);
+        writer.u64(self.next_interrupt as u64);
+    }
+
+    fn load_state(&mut self, reader: &mut Reader) -> Result<(), StateError> {
+        self.first_port = reader.u8()?;
+        self.second_port = reader.u8()?;
+        self.last_port_three = reader.u8()?;
+        self.last_port_five = reader.u8()?;
+        self.shiftx = reader.u8()?;
+        self.shifty = reader.u8()?;
+        self.shift_offset = reader.u16()?;
+        self.interrupt_num = reader.bool()?;
+        self.next_interrupt = reader.u64()? as i64;
+        Ok(())
+    }
+}
+
 fn main() {
     let mut machine = SpaceInvaders::new();
 
@@ -424,4 +532,20 @@ mod tests {
 
         assert_eq!(machine.input(3), 0xff);
     }
+
+    #[test]
+    fn save_state_restores_shift_register() {
+        let mut machine = SpaceInvaders::new();
+
+        machine.output(2, 2);
+        machine.output(4, 0xff);
+        machine.output(4, 0x3f);
+
+        let state = machine.cpu.save_state(&machine);
+        let mut restored = SpaceInvaders::new();
+        restored.restore(&state).unwrap();
+
+        assert_eq!(restored.input(3), 0xff);
+        assert_eq!(restored.framebuffer(), machine.framebuffer());
+    }
 }
@@ -0,0 +1,58 @@
+use clap::{App, Arg};
+use i8080::trace;
+
+use std::fs::File;
+use std::io::BufReader;
+use std::process;
+
+fn open(path: &str) -> BufReader<File> {
+    match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            process::exit(2);
+        }
+    }
+}
+
+fn main() {
+    let matches = App::new("tracediff")
+        .about("Reports the first line where two execution traces diverge")
+        .arg(Arg::new("EXPECTED")
+            .required(true)
+            .help("The reference trace"))
+        .arg(Arg::new("FOUND")
+            .required(true)
+            .help("The trace to check against it"))
+        .get_matches();
+
+    let expected = matches.value_of("EXPECTED").unwrap();
+    let found = matches.value_of("FOUND").unwrap();
+
+    let divergence = trace::compare(open(expected), open(found)).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(2);
+    });
+
+    let divergence = match divergence {
+        Some(divergence) => divergence,
+        None => {
+            println!("Traces match");
+            return;
+        }
+    };
+
+    if divergence.fields.is_empty() {
+        println!("Traces diverge at line {}: one trace ends early", divergence.line);
+    } else {
+        println!("Traces diverge at line {} in {}",
+                 divergence.line,
+                 divergence.fields.join(", "));
+    }
+
+    for (path, line) in [(expected, divergence.expected), (found, divergence.found)] {
+        println!("{}: {}", path, line.as_deref().unwrap_or("<end of trace>"));
+    }
+
+    process::exit(1);
+}
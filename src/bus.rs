@@ -0,0 +1,21 @@
+pub trait Bus {
+    /// Returns the byte at `address` without any side effects, for use by
+    /// debuggers and disassemblers.
+    fn peek(&self, address: u16) -> u8;
+
+    /// Reads the byte at `address` on behalf of the CPU. Memory-mapped devices
+    /// with read side effects should override this.
+    fn read(&mut self, address: u16) -> u8 {
+        self.peek(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8);
+
+    /// Copies `block` into the address space starting at `position`, bypassing
+    /// any write protection where the implementation supports it.
+    fn load(&mut self, block: &[u8], position: u16) {
+        for (offset, byte) in block.iter().enumerate() {
+            self.write(position.wrapping_add(offset as u16), *byte);
+        }
+    }
+}
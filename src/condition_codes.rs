@@ -26,20 +26,24 @@ impl ConditionCodes {
         self.p = (answer as u8).count_ones() % 2 == 0;
     }
 
-    pub fn set_ac(&mut self, answer: u8) {
-        self.ac = answer > 0xf;
+    /// Sets AC to the carry out of bit 3 of `lhs + rhs + carry`. The 8080
+    /// subtracts by adding the complement of the subtrahend with the borrow
+    /// inverted as the carry, so this also gives AC for subtraction.
+    pub fn set_ac(&mut self, lhs: u8, rhs: u8, carry: bool) {
+        self.ac = (lhs & 0xf) + (rhs & 0xf) + carry as u8 > 0xf;
     }
 
-    pub fn set_all(&mut self, answer: u16, ac_check: u8) {
-        self.set_all_except_carry(answer, ac_check);
+    /// Sets every flag from `answer`, the sum of `lhs`, `rhs` and `carry`.
+    pub fn set_all(&mut self, answer: u16, lhs: u8, rhs: u8, carry: bool) {
+        self.set_all_except_carry(answer, lhs, rhs, carry);
         self.set_cy(answer);
     }
 
-    pub fn set_all_except_carry(&mut self, answer: u16, ac_check: u8) {
+    pub fn set_all_except_carry(&mut self, answer: u16, lhs: u8, rhs: u8, carry: bool) {
         self.set_z(answer);
         self.set_s(answer);
         self.set_p(answer);
-        self.set_ac(ac_check);
+        self.set_ac(lhs, rhs, carry);
     }
 
     pub fn set_all_except_ac(&mut self, answer: u16) {
@@ -48,6 +52,13 @@ impl ConditionCodes {
         self.set_p(answer);
         self.set_cy(answer);
     }
+
+    /// Sets the flags after a logical operation, which always clears CY.
+    /// AND sets AC from bit 3 of its operands; the others clear it.
+    pub fn set_logical(&mut self, answer: u8, ac: bool) {
+        self.set_all_except_ac(answer as u16);
+        self.ac = ac;
+    }
 }
 
 // FLAG WORD:
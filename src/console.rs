@@ -0,0 +1,390 @@
+use std::io::{self, BufRead, Write};
+
+use crate::bus::Bus;
+use crate::cpu::{Cpu, Machine};
+use crate::debugger::{Debugger, StopReason};
+use crate::disasm::Instruction;
+use crate::observer::Access;
+
+const HELP: &str = "\
+step [N]          execute N instructions (s)
+continue          run until a breakpoint, watchpoint or error (c)
+finish            run until the current subroutine returns (f)
+back [N]          step back N instructions, replaying from the last snapshot
+break ADDR        stop before executing ADDR (b)
+watch ADDR        stop after a write to ADDR (w)
+rwatch ADDR       stop after a read of ADDR
+awatch ADDR       stop after a read or write of ADDR
+delete ADDR       remove breakpoints and watchpoints at ADDR (d)
+info              list breakpoints and watchpoints (i)
+regs              show registers and flags (r)
+set NAME VALUE    set a register (a-l, bc, de, hl, sp, pc), flag (s, z, ac, p, cy) or ie
+x ADDR [LEN]      dump memory
+list [ADDR] [N]   disassemble N instructions, by default around PC (l)
+history [N]       show the last N instructions run with their registers
+quit              exit (q)
+
+Numbers are hex. An empty line repeats the last command.";
+
+/// Commands a machine adds to the console. Each returns `None` if the
+/// machine doesn't know the command. `help` is passed on too, after the
+/// console's own help, so that the machine can list its commands.
+pub type Commands<'a, M> = dyn FnMut(&mut M, &str, &[&str]) -> Option<Result<(), String>> + 'a;
+
+/// Parses a hex number, optionally written with a `0x` prefix or `H`
+/// suffix.
+pub fn parse_number(text: &str) -> Result<u16, String> {
+    let digits = text.strip_prefix("0x")
+        .or_else(|| text.strip_suffix(|c| c == 'h' || c == 'H'))
+        .unwrap_or(text);
+
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid number: {}", text))
+}
+
+fn is_return(opcode: u8) -> bool {
+    matches!(opcode, 0xc9 | 0xd9 | 0xc0 | 0xc8 | 0xd0 | 0xd8 | 0xe0 | 0xe8 | 0xf0 | 0xf8)
+}
+
+/// An interactive debugger prompt for a CPU and the machine it runs in.
+pub struct Console<B, M> {
+    pub cpu: Cpu<B, Debugger>,
+    pub machine: M,
+}
+
+impl<B: Bus, M: Machine> Console<B, M> {
+    pub fn new(cpu: Cpu<B, Debugger>, machine: M) -> Self {
+        Console { cpu, machine }
+    }
+
+    /// Reads commands from `input` until `quit` or the end of the input.
+    /// Commands the console doesn't know are passed to `commands`.
+    pub fn run<R: BufRead>(&mut self, input: R, commands: &mut Commands<M>) {
+        let mut last = String::new();
+        let mut lines = input.lines();
+
+        self.print_instruction(*self.cpu.pc);
+
+        loop {
+            print!("(i8080) ");
+            io::stdout().flush().unwrap();
+
+            let mut line = match lines.next() {
+                Some(Ok(line)) => line,
+                _ => break,
+            };
+
+            if line.trim().is_empty() {
+                line = last.clone();
+            } else {
+                last = line.clone();
+            }
+
+            match self.command(&line, commands) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(error) => println!("{}", error),
+            }
+        }
+    }
+
+    fn step(&mut self, count: u16) -> StopReason {
+        let mut remaining = count;
+        self.cpu.run_until(&mut self.machine, |_| {
+            remaining -= 1;
+            remaining == 0
+        })
+    }
+
+    /// Runs until an instruction returns from the current subroutine.
+    fn finish(&mut self) -> StopReason {
+        let sp = *self.cpu.sp;
+        self.cpu.run_until(&mut self.machine, |cpu| {
+            let returned = cpu.observer.last_fetch().is_some_and(|(_, opcode)| is_return(*opcode));
+            returned && *cpu.sp > sp
+        })
+    }
+
+    fn instruction(&self, address: u16) -> Instruction {
+        let bytes = [0, 1, 2].map(|i| self.cpu.memory.peek(address.wrapping_add(i)));
+        Instruction::decode(&bytes, address).unwrap()
+    }
+
+    pub fn print_instruction(&self, address: u16) {
+        let instruction = self.instruction(address);
+        let bytes = instruction.bytes()
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let marker = if address == *self.cpu.pc { "=>" } else { "  " };
+
+        println!("{} {:04X}  {:<8}  {}", marker, address, bytes, instruction);
+    }
+
+    /// Decoding backwards is ambiguous, so start from the earliest nearby
+    /// address whose instructions run into `address`.
+    fn listing_start(&self, address: u16) -> u16 {
+        for back in (1..=9u16).rev() {
+            let start = address.wrapping_sub(back);
+            let mut offset = 0;
+
+            while offset < back {
+                offset += self.instruction(start.wrapping_add(offset)).bytes().len() as u16;
+            }
+
+            if offset == back {
+                return start;
+            }
+        }
+
+        address
+    }
+
+    fn report(&self, stop: StopReason) {
+        match stop {
+            StopReason::Condition => {}
+            StopReason::Breakpoint { pc } => println!("Breakpoint at {:04X}", pc),
+            StopReason::Watchpoint { address, value, access } => {
+                println!("{:?} of {:02X} at {:04X}", access, value, address);
+            }
+            StopReason::Error(error) => println!("{}", error),
+        }
+
+        self.print_instruction(*self.cpu.pc);
+    }
+
+    fn hexdump(&self, address: u16, len: u16) {
+        for row in (0..len).step_by(16) {
+            let start = address.wrapping_add(row);
+            let bytes = (0..16.min(len - row))
+                .map(|i| self.cpu.memory.peek(start.wrapping_add(i)))
+                .collect::<Vec<_>>();
+            let hex = bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>();
+            let text = bytes.iter()
+                .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+                .collect::<String>();
+
+            println!("{:04X}  {:<47}  {}", start, hex.join(" "), text);
+        }
+    }
+
+    fn set(&mut self, name: &str, value: u16) -> Result<(), String> {
+        let cpu = &mut self.cpu;
+        let flag = value != 0;
+
+        match name {
+            "a" => cpu.a = value.into(),
+            "b" => cpu.b = value.into(),
+            "c" => cpu.c = value.into(),
+            "d" => cpu.d = value.into(),
+            "e" => cpu.e = value.into(),
+            "h" => cpu.h = value.into(),
+            "l" => cpu.l = value.into(),
+            "bc" => {
+                cpu.b = (value >> 8).into();
+                cpu.c = value.into();
+            }
+            "de" => {
+                cpu.d = (value >> 8).into();
+                cpu.e = value.into();
+            }
+            "hl" => {
+                cpu.h = (value >> 8).into();
+                cpu.l = value.into();
+            }
+            "sp" => cpu.sp = value.into(),
+            "pc" => cpu.pc = value.into(),
+            "s" => cpu.conditions.s = flag,
+            "z" => cpu.conditions.z = flag,
+            "ac" => cpu.conditions.ac = flag,
+            "p" => cpu.conditions.p = flag,
+            "cy" => cpu.conditions.cy = flag,
+            "ie" => cpu.int_enable = flag,
+            _ => return Err(format!("unknown register: {}", name)),
+        }
+
+        Ok(())
+    }
+
+    fn watch(&mut self, arguments: &[&str], accesses: &[Access]) -> Result<(), String> {
+        match arguments {
+            [address] => {
+                let address = parse_number(address)?;
+                for &access in accesses {
+                    self.cpu.observer.watchpoints.insert((address, access));
+                }
+
+                Ok(())
+            }
+            _ => Err(String::from("expected an address")),
+        }
+    }
+
+    /// Runs one command, returning `false` to quit.
+    pub fn command(&mut self, line: &str, commands: &mut Commands<M>) -> Result<bool, String> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let (command, arguments) = match words.split_first() {
+            Some((command, arguments)) => (*command, arguments),
+            None => return Ok(true),
+        };
+        let number = |index: usize, default: u16| {
+            arguments.get(index).map_or(Ok(default), |text| parse_number(text))
+        };
+
+        match command {
+            "help" | "h" | "?" => {
+                println!("{}", HELP);
+                commands(&mut self.machine, "help", arguments).transpose()?;
+            }
+            "step" | "s" => {
+                let count = number(0, 1)?.max(1);
+                let stop = self.step(count);
+                self.report(stop);
+            }
+            "continue" | "c" => {
+                let stop = self.cpu.run_until(&mut self.machine, |_| false);
+                self.report(stop);
+            }
+            "finish" | "f" => {
+                let stop = self.finish();
+                if stop == StopReason::Condition {
+                    println!("Returned to {:04X}", *self.cpu.pc);
+                }
+
+                self.report(stop);
+            }
+            "back" => {
+                if !self.cpu.step_back(&mut self.machine, number(0, 1)? as u64) {
+                    return Err(String::from("can't step back that far"));
+                }
+
+                self.print_instruction(*self.cpu.pc);
+            }
+            "break" | "b" => {
+                self.cpu.observer.breakpoints.insert(number(0, *self.cpu.pc)?);
+            }
+            "watch" | "w" => self.watch(arguments, &[Access::Write])?,
+            "rwatch" => self.watch(arguments, &[Access::Read])?,
+            "awatch" => self.watch(arguments, &[Access::Read, Access::Write])?,
+            "delete" | "d" => {
+                let address = number(0, *self.cpu.pc)?;
+                self.cpu.observer.breakpoints.remove(&address);
+                self.cpu.observer.watchpoints.retain(|&(watched, _)| watched != address);
+            }
+            "info" | "i" => {
+                for address in &self.cpu.observer.breakpoints {
+                    println!("break   {:04X}", address);
+                }
+
+                for (address, access) in &self.cpu.observer.watchpoints {
+                    println!("watch   {:04X} {:?}", address, access);
+                }
+            }
+            "regs" | "r" => {
+                println!("{:?}", self.cpu);
+                println!("cycles {} ie {} halted {}",
+                         self.cpu.cycles, self.cpu.int_enable, self.cpu.halted);
+            }
+            "set" => match arguments {
+                [name, value] => self.set(&name.to_ascii_lowercase(), parse_number(value)?)?,
+                _ => return Err(String::from("expected a name and a value")),
+            },
+            "x" => self.hexdump(number(0, *self.cpu.pc)?, number(1, 0x40)?),
+            "list" | "l" => {
+                let count = number(1, 10)?;
+                let mut address = match arguments.first() {
+                    Some(text) => parse_number(text)?,
+                    None => self.listing_start(*self.cpu.pc),
+                };
+
+                for _ in 0..count {
+                    self.print_instruction(address);
+                    address = self.instruction(address).next_address();
+                }
+            }
+            "history" => {
+                let count = number(0, 10)? as usize;
+
+                if let Some(history) = &self.cpu.history {
+                    for entry in history.iter().skip(history.len().saturating_sub(count)) {
+                        println!("{}", entry);
+                    }
+                }
+            }
+            "quit" | "q" => return Ok(false),
+            _ => match commands(&mut self.machine, command, arguments) {
+                Some(result) => result?,
+                None => return Err(format!("unknown command: {} (try help)", command)),
+            },
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, Memory};
+
+    /// A machine whose input ports all read the value it holds.
+    struct Counter(u8);
+
+    impl Machine for Counter {
+        fn input(&mut self, _: u8) -> u8 { self.0 }
+        fn output(&mut self, _: u8, _: u8) {}
+    }
+
+    fn console(source: &str) -> Console<Memory, Counter> {
+        let assembly = asm::assemble(source).unwrap();
+        let mut cpu = Cpu::with_observer(Memory::default(), Debugger::new());
+        cpu.load_into_rom(&assembly.bytes, assembly.origin);
+        cpu.sp = 0x100u16.into();
+        Console::new(cpu, Counter(0))
+    }
+
+    #[test]
+    fn commands() {
+        let mut console = console("IN 0\nINR A\nCALL SUB\nHLT\nSUB: INR A\nRET");
+        let mut none = |_: &mut Counter, _: &str, _: &[&str]| None;
+
+        assert_eq!(console.command("set a 40", &mut none), Ok(true));
+        assert_eq!(console.command("set pc 2", &mut none), Ok(true));
+        assert_eq!(*console.cpu.a, 0x40);
+        assert_eq!(*console.cpu.pc, 2);
+
+        console.cpu.pc = 0u16.into();
+        console.machine.0 = 0x41;
+        assert_eq!(console.command("s 2", &mut none), Ok(true));
+        assert_eq!(*console.cpu.a, 0x42);
+
+        assert_eq!(console.command("b 8", &mut none), Ok(true));
+        assert_eq!(console.command("c", &mut none), Ok(true));
+        assert_eq!(*console.cpu.pc, 8);
+        assert_eq!(console.command("finish", &mut none), Ok(true));
+        assert_eq!((*console.cpu.pc, *console.cpu.a), (6, 0x43));
+
+        assert_eq!(console.command("set q 1", &mut none), Err("unknown register: q".into()));
+        assert_eq!(console.command("jump", &mut none),
+                   Err("unknown command: jump (try help)".into()));
+        assert_eq!(console.command("q", &mut none), Ok(false));
+    }
+
+    #[test]
+    fn machine_commands() {
+        let mut console = console("IN 0");
+        let mut commands = |counter: &mut Counter, command: &str, arguments: &[&str]| {
+            match (command, arguments) {
+                ("count", [value]) => Some(parse_number(value).map(|value| {
+                    counter.0 = value as u8;
+                })),
+                _ => None,
+            }
+        };
+
+        assert_eq!(console.command("count 2A", &mut commands), Ok(true));
+        assert_eq!(console.command("s", &mut commands), Ok(true));
+        assert_eq!(*console.cpu.a, 0x2a);
+        assert_eq!(console.command("count zz", &mut commands), Err("invalid number: zz".into()));
+    }
+}
@@ -0,0 +1,706 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::{error, fmt};
+
+use crate::bus::Bus;
+use crate::cpu::{Cpu, CpuError, Machine};
+use crate::observer::Observer;
+
+/// Where programs are loaded and start.
+pub const TPA: u16 = 0x100;
+
+/// The BDOS entry point, which programs reach through `CALL 5`. Programs
+/// read it from address 6 as the top of usable memory.
+pub const BDOS: u16 = 0xfe00;
+
+/// The BIOS jump table, whose warm boot entry address 0 jumps to.
+pub const BIOS: u16 = 0xff00;
+
+const BIOS_FUNCTIONS: u16 = 17;
+
+/// Each BIOS entry jumps to its own `RET` here, which is trapped so that
+/// `Cpm::step` can carry out the function.
+const BIOS_STUBS: u16 = BIOS + 0x80;
+
+const DEFAULT_FCB: u16 = 0x5c;
+const DEFAULT_DMA: u16 = 0x80;
+const FCB_LEN: usize = 36;
+const RECORD_LEN: usize = 128;
+const RECORDS_PER_EXTENT: u64 = 128;
+
+/// Returned for console input past the end and pads partial records.
+const EOF: u8 = 0x1a;
+
+#[derive(Debug)]
+pub enum CpmError {
+    Cpu(CpuError),
+    /// Reading or writing the console failed.
+    Io(io::Error),
+    /// The program called a BDOS function that isn't emulated.
+    Unsupported { function: u8 },
+}
+
+impl fmt::Display for CpmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CpmError::Cpu(ref error) => error.fmt(f),
+            CpmError::Io(ref error) => write!(f, "console: {}", error),
+            CpmError::Unsupported { function } => {
+                write!(f, "unsupported BDOS function {}", function)
+            }
+        }
+    }
+}
+
+impl error::Error for CpmError {}
+
+impl From<CpuError> for CpmError {
+    fn from(error: CpuError) -> Self {
+        CpmError::Cpu(error)
+    }
+}
+
+impl From<io::Error> for CpmError {
+    fn from(error: io::Error) -> Self {
+        CpmError::Io(error)
+    }
+}
+
+/// Runs CP/M 2.2 programs by carrying out their BDOS and BIOS calls on the
+/// host. Every drive is the same host directory, and the BIOS disk functions
+/// report errors since there are no sectors to read.
+pub struct Cpm<R = io::Stdin, W = io::Stdout> {
+    directory: PathBuf,
+    input: R,
+    output: W,
+    dma: u16,
+    /// The names left for search next to return, last first.
+    found: Vec<[u8; 11]>,
+}
+
+impl Cpm {
+    /// Uses the terminal as the console.
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Cpm::with_console(directory, io::stdin(), io::stdout())
+    }
+}
+
+impl<R: Read, W: Write> Cpm<R, W> {
+    pub fn with_console<P: Into<PathBuf>>(directory: P, input: R, output: W) -> Self {
+        Cpm { directory: directory.into(), input, output, dma: DEFAULT_DMA, found: Vec::new() }
+    }
+
+    /// Everything written to the console so far, when it is a buffer.
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+
+    /// Sets up the zero page, BDOS and BIOS, copies `program` to the TPA with
+    /// `arguments` as its command tail and points the CPU at it. Returning
+    /// from the program warm boots, which exits.
+    pub fn load<B, O>(&mut self, cpu: &mut Cpu<B, O>, program: &[u8], arguments: &str)
+        where B: Bus,
+              O: Observer,
+    {
+        let arguments = arguments.trim().to_ascii_uppercase();
+        let mut words = arguments.split_whitespace();
+        let mut page = [0; 0x100];
+        let fcb = DEFAULT_FCB as usize;
+
+        page[0..3].copy_from_slice(&jump(BIOS + 3));
+        page[5..8].copy_from_slice(&jump(BDOS));
+        page[fcb..fcb + 12].copy_from_slice(&parse_fcb(words.next().unwrap_or_default()));
+        page[fcb + 16..fcb + 28].copy_from_slice(&parse_fcb(words.next().unwrap_or_default()));
+
+        let tail = arguments.as_bytes();
+        let tail = &tail[..tail.len().min(RECORD_LEN - 2)];
+        if !tail.is_empty() {
+            page[0x80] = tail.len() as u8 + 1;
+            page[0x81] = b' ';
+            page[0x82..0x82 + tail.len()].copy_from_slice(tail);
+        }
+
+        let table = (0..BIOS_FUNCTIONS).flat_map(|i| jump(BIOS_STUBS + i)).collect::<Vec<_>>();
+
+        cpu.memory.load(&page, 0);
+        cpu.memory.load(&[0xc9], BDOS);
+        cpu.memory.load(&table, BIOS);
+        cpu.memory.load(&[0xc9; BIOS_FUNCTIONS as usize], BIOS_STUBS);
+        cpu.memory.load(program, TPA);
+        cpu.memory.load(&[0, 0], BDOS - 2);
+
+        cpu.traps.insert(BDOS);
+        cpu.traps.extend(BIOS_STUBS..BIOS_STUBS + BIOS_FUNCTIONS);
+        cpu.sp = (BDOS - 2).into();
+        cpu.pc = TPA.into();
+        cpu.halted = false;
+        self.dma = DEFAULT_DMA;
+        self.found.clear();
+    }
+
+    /// Runs the next instruction. If the CPU has called a BDOS or BIOS
+    /// function, it carries out the function instead, leaving the `RET` back
+    /// to the program for the following step. Returns `false` once the
+    /// program has exited.
+    pub fn step<B: Bus, O: Observer>(&mut self, cpu: &mut Cpu<B, O>) -> Result<bool, CpmError> {
+        let stubs = BIOS_STUBS..BIOS_STUBS + BIOS_FUNCTIONS;
+        let result = match cpu.emulate(self) {
+            Err(CpuError::Trap { pc: BDOS }) => self.bdos(cpu)?,
+            Err(CpuError::Trap { pc }) if stubs.contains(&pc) => self.bios(cpu, pc - BIOS_STUBS)?,
+            result => {
+                result?;
+                return Ok(true);
+            }
+        };
+
+        match result {
+            Some(result) => {
+                set_result(cpu, result);
+                Ok(true)
+            }
+            None => {
+                self.output.flush()?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Runs the program until it exits.
+    pub fn run<B: Bus, O: Observer>(&mut self, cpu: &mut Cpu<B, O>) -> Result<(), CpmError> {
+        while self.step(cpu)? {}
+        Ok(())
+    }
+
+    /// Carries out the BDOS function in C, returning the value for HL or
+    /// `None` if the program exited.
+    fn bdos<B, O>(&mut self, cpu: &mut Cpu<B, O>) -> Result<Option<u16>, CpmError>
+        where B: Bus,
+              O: Observer,
+    {
+        let function = *cpu.c;
+        let de = (*cpu.d as u16) << 8 | *cpu.e as u16;
+        let e = *cpu.e;
+
+        let result = match function {
+            0 => return Ok(None),
+            1 => {
+                let byte = self.console_in()?;
+                self.console_out(&[byte])?;
+                byte as u16
+            }
+            3 => self.console_in()? as u16,
+            2 | 4 => {
+                self.console_out(&[e])?;
+                0
+            }
+            5 | 7 | 8 | 11 | 14 | 25 | 32 => 0,
+            6 => match e {
+                0xff => self.console_in()? as u16,
+                0xfe => 0,
+                _ => {
+                    self.console_out(&[e])?;
+                    0
+                }
+            },
+            9 => {
+                let text = (0..=0xffff)
+                    .map(|offset| cpu.memory.peek(de.wrapping_add(offset)))
+                    .take_while(|&byte| byte != b'$')
+                    .collect::<Vec<_>>();
+                self.console_out(&text)?;
+                0
+            }
+            10 => {
+                self.read_line(cpu, de)?;
+                0
+            }
+            12 => 0x0022,
+            13 => {
+                self.dma = DEFAULT_DMA;
+                0
+            }
+            15 => self.open(cpu, de),
+            16 => self.close(cpu, de),
+            17 => self.search_first(cpu, de),
+            18 => self.search_next(cpu),
+            19 => self.delete(cpu, de),
+            20 => self.read(cpu, de, false),
+            21 => self.write(cpu, de, false),
+            22 => self.make(cpu, de),
+            23 => self.rename(cpu, de),
+            24 => 1,
+            26 => {
+                self.dma = de;
+                0
+            }
+            33 => self.read(cpu, de, true),
+            34 | 40 => self.write(cpu, de, true),
+            35 => self.size(cpu, de),
+            36 => {
+                let mut fcb = read_fcb(cpu, de);
+                let record = sequential_record(&fcb);
+                set_random_record(&mut fcb, record);
+                cpu.memory.load(&fcb, de);
+                0
+            }
+            _ => return Err(CpmError::Unsupported { function }),
+        };
+
+        Ok(Some(result))
+    }
+
+    /// Carries out a BIOS function, numbered by its place in the jump table.
+    fn bios<B, O>(&mut self, cpu: &mut Cpu<B, O>, function: u16) -> Result<Option<u16>, CpmError>
+        where B: Bus,
+              O: Observer,
+    {
+        let bc = (*cpu.b as u16) << 8 | *cpu.c as u16;
+
+        let result = match function {
+            0 | 1 => return Ok(None),
+            3 => self.console_in()? as u16,
+            4 => {
+                self.console_out(&[*cpu.c])?;
+                0
+            }
+            7 => EOF as u16,
+            12 => {
+                self.dma = bc;
+                0
+            }
+            // No disk parameter header, so SELDSK fails, and READ and WRITE
+            // report errors.
+            13 | 14 => 1,
+            15 => 0xff,
+            16 => bc,
+            _ => 0,
+        };
+
+        Ok(Some(result))
+    }
+
+    fn console_in(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        self.output.flush()?;
+
+        Ok(match self.input.read(&mut byte)? {
+            0 => EOF,
+            _ if byte[0] == b'\n' => b'\r',
+            _ => byte[0],
+        })
+    }
+
+    fn console_out(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.output.write_all(bytes)?;
+        self.output.flush()
+    }
+
+    /// Reads a line into the buffer at `address`, which holds its size
+    /// followed by the length read and the characters. Characters are echoed
+    /// as they are typed, as long as they fit, and the line is ended with a
+    /// carriage return.
+    fn read_line<B, O>(&mut self, cpu: &mut Cpu<B, O>, address: u16) -> io::Result<()>
+        where B: Bus,
+              O: Observer,
+    {
+        let max = cpu.memory.peek(address) as usize;
+        let mut line = Vec::new();
+
+        loop {
+            match self.console_in()? {
+                b'\r' | EOF => break,
+                _ if line.len() == max => {}
+                byte => {
+                    self.console_out(&[byte])?;
+                    line.push(byte);
+                }
+            }
+        }
+
+        self.console_out(b"\r")?;
+        line.insert(0, line.len() as u8);
+        cpu.memory.load(&line, address.wrapping_add(1));
+        Ok(())
+    }
+
+    /// The files in the directory whose names fit in an FCB, with those
+    /// names.
+    fn entries(&self) -> Vec<([u8; 11], PathBuf)> {
+        let mut entries = fs::read_dir(&self.directory)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
+            .filter_map(|entry| Some((fcb_name(entry.file_name().to_str()?)?, entry.path())))
+            .collect::<Vec<_>>();
+
+        entries.sort();
+        entries
+    }
+
+    /// The file named by the FCB, or where to create it.
+    fn path(&self, name: &[u8; 11]) -> PathBuf {
+        match self.entries().into_iter().find(|(entry, _)| entry == name) {
+            Some((_, path)) => path,
+            None => self.directory.join(host_name(name)),
+        }
+    }
+
+    fn open<B: Bus, O: Observer>(&mut self, cpu: &mut Cpu<B, O>, address: u16) -> u16 {
+        let mut fcb = read_fcb(cpu, address);
+        let len = match fs::metadata(self.path(&name(&fcb))) {
+            Ok(metadata) if metadata.is_file() => metadata.len(),
+            _ => return 0xff,
+        };
+
+        let extent = sequential_record(&fcb) / RECORDS_PER_EXTENT * RECORDS_PER_EXTENT;
+        let records = len.div_ceil(RECORD_LEN as u64);
+        fcb[15] = records.saturating_sub(extent).min(RECORDS_PER_EXTENT) as u8;
+        cpu.memory.load(&fcb, address);
+        0
+    }
+
+    fn close<B: Bus, O: Observer>(&mut self, cpu: &mut Cpu<B, O>, address: u16) -> u16 {
+        if self.path(&name(&read_fcb(cpu, address))).is_file() { 0 } else { 0xff }
+    }
+
+    fn search_first<B: Bus, O: Observer>(&mut self, cpu: &mut Cpu<B, O>, address: u16) -> u16 {
+        let fcb = read_fcb(cpu, address);
+        let pattern = name(&fcb);
+
+        self.found = self.entries()
+            .into_iter()
+            .map(|(entry, _)| entry)
+            .filter(|entry| fcb[0] == b'?' || matches(&pattern, entry))
+            .rev()
+            .collect();
+
+        self.search_next(cpu)
+    }
+
+    /// Writes the next directory entry found to the DMA buffer.
+    fn search_next<B: Bus, O: Observer>(&mut self, cpu: &mut Cpu<B, O>) -> u16 {
+        let name = match self.found.pop() {
+            Some(name) => name,
+            None => return 0xff,
+        };
+        let len = fs::metadata(self.path(&name)).map_or(0, |metadata| metadata.len());
+        let mut entry = [0; 32];
+
+        entry[1..12].copy_from_slice(&name);
+        entry[15] = len.div_ceil(RECORD_LEN as u64).min(RECORDS_PER_EXTENT) as u8;
+        cpu.memory.load(&entry, self.dma);
+        0
+    }
+
+    fn delete<B: Bus, O: Observer>(&mut self, cpu: &mut Cpu<B, O>, address: u16) -> u16 {
+        let pattern = name(&read_fcb(cpu, address));
+        let mut deleted = false;
+
+        for (entry, path) in self.entries() {
+            if matches(&pattern, &entry) {
+                deleted |= fs::remove_file(path).is_ok();
+            }
+        }
+
+        if deleted { 0 } else { 0xff }
+    }
+
+    /// Reads a record into the DMA buffer, from the sequential position or
+    /// the random record, which also becomes the sequential position.
+    fn read<B, O>(&mut self, cpu: &mut Cpu<B, O>, address: u16, random: bool) -> u16
+        where B: Bus,
+              O: Observer,
+    {
+        let mut fcb = read_fcb(cpu, address);
+        let record = if random { random_record(&fcb) } else { sequential_record(&fcb) };
+        let mut buffer = [EOF; RECORD_LEN];
+
+        let read = File::open(self.path(&name(&fcb))).and_then(|mut file| {
+            file.seek(SeekFrom::Start(record * RECORD_LEN as u64))?;
+            read_record(&mut file, &mut buffer)
+        });
+
+        match read {
+            Ok(0) => return 1,
+            Ok(_) => {}
+            Err(_) => return 0xff,
+        }
+
+        set_sequential_record(&mut fcb, if random { record } else { record + 1 });
+        cpu.memory.load(&fcb, address);
+        cpu.memory.load(&buffer, self.dma);
+        0
+    }
+
+    fn write<B, O>(&mut self, cpu: &mut Cpu<B, O>, address: u16, random: bool) -> u16
+        where B: Bus,
+              O: Observer,
+    {
+        let mut fcb = read_fcb(cpu, address);
+        let record = if random { random_record(&fcb) } else { sequential_record(&fcb) };
+        let buffer = (0..RECORD_LEN as u16)
+            .map(|offset| cpu.memory.peek(self.dma.wrapping_add(offset)))
+            .collect::<Vec<_>>();
+
+        let file = OpenOptions::new().write(true).open(self.path(&name(&fcb)));
+        let written = file.and_then(|mut file| {
+            file.seek(SeekFrom::Start(record * RECORD_LEN as u64))?;
+            file.write_all(&buffer)
+        });
+
+        if written.is_err() {
+            // Disk full, the closest CP/M has to an I/O error.
+            return 2;
+        }
+
+        set_sequential_record(&mut fcb, if random { record } else { record + 1 });
+        cpu.memory.load(&fcb, address);
+        0
+    }
+
+    fn make<B: Bus, O: Observer>(&mut self, cpu: &mut Cpu<B, O>, address: u16) -> u16 {
+        let mut fcb = read_fcb(cpu, address);
+
+        if File::create(self.path(&name(&fcb))).is_err() {
+            return 0xff;
+        }
+
+        fcb[15] = 0;
+        cpu.memory.load(&fcb, address);
+        0
+    }
+
+    /// Renames the file named by the FCB to the name 16 bytes into it.
+    fn rename<B: Bus, O: Observer>(&mut self, cpu: &mut Cpu<B, O>, address: u16) -> u16 {
+        let fcb = read_fcb(cpu, address);
+        let mut to = [0; 11];
+        to.copy_from_slice(&fcb[17..28]);
+
+        match fs::rename(self.path(&name(&fcb)), self.path(&to)) {
+            Ok(()) => 0,
+            Err(_) => 0xff,
+        }
+    }
+
+    /// Sets the random record to the number of records in the file.
+    fn size<B: Bus, O: Observer>(&mut self, cpu: &mut Cpu<B, O>, address: u16) -> u16 {
+        let mut fcb = read_fcb(cpu, address);
+
+        let len = match fs::metadata(self.path(&name(&fcb))) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => return 0xff,
+        };
+
+        set_random_record(&mut fcb, len.div_ceil(RECORD_LEN as u64));
+
+        cpu.memory.load(&fcb, address);
+        0
+    }
+}
+
+impl<R, W> Machine for Cpm<R, W> {
+    fn input(&mut self, _: u8) -> u8 { 0 }
+    fn output(&mut self, _: u8, _: u8) {}
+}
+
+fn jump(address: u16) -> [u8; 3] {
+    [0xc3, address as u8, (address >> 8) as u8]
+}
+
+/// BDOS functions return a byte in A and L, and a word in HL and BA.
+fn set_result<B, O>(cpu: &mut Cpu<B, O>, result: u16) {
+    cpu.a = (result as u8).into();
+    cpu.l = (result as u8).into();
+    cpu.b = ((result >> 8) as u8).into();
+    cpu.h = ((result >> 8) as u8).into();
+}
+
+/// Fills as much of `buffer` from `file` as it has left, returning how much.
+fn read_record(file: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+
+    while read < buffer.len() {
+        match file.read(&mut buffer[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+
+    Ok(read)
+}
+
+fn read_fcb<B: Bus, O>(cpu: &Cpu<B, O>, address: u16) -> [u8; FCB_LEN] {
+    let mut fcb = [0; FCB_LEN];
+
+    for (offset, byte) in fcb.iter_mut().enumerate() {
+        *byte = cpu.memory.peek(address.wrapping_add(offset as u16));
+    }
+
+    fcb
+}
+
+/// The name and type in an FCB, without the attribute bits CP/M keeps in
+/// their high bits.
+fn name(fcb: &[u8]) -> [u8; 11] {
+    let mut name = [0; 11];
+
+    for (byte, &from) in name.iter_mut().zip(&fcb[1..12]) {
+        *byte = (from & 0x7f).to_ascii_uppercase();
+    }
+
+    name
+}
+
+fn matches(pattern: &[u8; 11], name: &[u8; 11]) -> bool {
+    pattern.iter().zip(name).all(|(&pattern, &byte)| pattern == b'?' || pattern == byte)
+}
+
+/// The record that sequential reads and writes use next, from the current
+/// record, extent and module bytes.
+fn sequential_record(fcb: &[u8]) -> u64 {
+    (fcb[14] as u64 * 32 + (fcb[12] & 0x1f) as u64) * RECORDS_PER_EXTENT + (fcb[32] & 0x7f) as u64
+}
+
+fn set_sequential_record(fcb: &mut [u8], record: u64) {
+    fcb[32] = (record % RECORDS_PER_EXTENT) as u8;
+    fcb[12] = (record / RECORDS_PER_EXTENT % 32) as u8;
+    fcb[14] = (record / RECORDS_PER_EXTENT / 32) as u8;
+}
+
+fn random_record(fcb: &[u8]) -> u64 {
+    (fcb[33] as u64) | (fcb[34] as u64) << 8 | (fcb[35] as u64) << 16
+}
+
+fn set_random_record(fcb: &mut [u8], record: u64) {
+    fcb[33] = record as u8;
+    fcb[34] = (record >> 8) as u8;
+    fcb[35] = (record >> 16) as u8;
+}
+
+/// Converts a command line argument such as `B:FOO.*` into the drive, name
+/// and type of an FCB.
+fn parse_fcb(argument: &str) -> [u8; 12] {
+    let mut fcb = [b' '; 12];
+    let (drive, file) = match argument.as_bytes() {
+        [drive @ b'A'..=b'P', b':', ..] => (drive - b'A' + 1, &argument[2..]),
+        _ => (0, argument),
+    };
+    let (stem, extension) = file.split_once('.').unwrap_or((file, ""));
+
+    fcb[0] = drive;
+    fill(&mut fcb[1..9], stem);
+    fill(&mut fcb[9..12], extension);
+    fcb
+}
+
+/// Copies `text` into a space padded field, expanding `*` to `?`s.
+fn fill(field: &mut [u8], text: &str) {
+    for (i, byte) in text.bytes().take(field.len()).enumerate() {
+        if byte == b'*' {
+            field[i..].fill(b'?');
+            break;
+        }
+
+        field[i] = byte;
+    }
+}
+
+/// The FCB name of a host file, if it is a valid 8.3 name.
+fn fcb_name(file: &str) -> Option<[u8; 11]> {
+    let (stem, extension) = file.split_once('.').unwrap_or((file, ""));
+    let valid = |part: &str, len| {
+        !part.is_empty() && part.len() <= len
+            && part.bytes().all(|byte| byte.is_ascii_graphic() && !b".*?:".contains(&byte))
+    };
+
+    if !valid(stem, 8) || !(extension.is_empty() || valid(extension, 3)) {
+        return None;
+    }
+
+    let mut name = [b' '; 11];
+    fill(&mut name[..8], &stem.to_ascii_uppercase());
+    fill(&mut name[8..], &extension.to_ascii_uppercase());
+    Some(name)
+}
+
+/// The host file name for a new file, such as `FOO.TXT`.
+fn host_name(name: &[u8; 11]) -> String {
+    let part = |bytes: &[u8]| String::from_utf8_lossy(bytes).trim_end().to_owned();
+    let (stem, extension) = (part(&name[..8]), part(&name[8..]));
+
+    if extension.is_empty() { stem } else { format!("{}.{}", stem, extension) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+
+    fn run(source: &str, arguments: &str, input: &[u8], directory: PathBuf) -> String {
+        let assembly = asm::assemble(source).unwrap();
+        let mut cpu = Cpu::new();
+        let mut cpm = Cpm::with_console(directory, input, Vec::new());
+
+        cpm.load(&mut cpu, &assembly.bytes, arguments);
+        cpm.run(&mut cpu).unwrap();
+        String::from_utf8(cpm.output().clone()).unwrap()
+    }
+
+    #[test]
+    fn console() {
+        let source = "ORG 100H\n\
+                      MVI C,9\nLXI D,HELLO\nCALL 5\n\
+                      MVI C,10\nLXI D,LINE\nCALL 5\n\
+                      MVI C,1\nCALL 5\n\
+                      MVI C,2\nLDA LINE+1\nADI '0'\nMOV E,A\nCALL 5\n\
+                      MVI C,2\nLDA 5DH\nMOV E,A\nCALL 5\n\
+                      LHLD 1\nPCHL\n\
+                      HELLO: DB 'Hello$'\n\
+                      LINE: DB 4,0,0,0,0,0";
+
+        assert_eq!(run(source, "foo bar", b"abcdef\nx", PathBuf::new()), "Helloabcd\rx4F");
+    }
+
+    #[test]
+    fn files() {
+        let directory = std::env::temp_dir().join(format!("i8080-cpm-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("old.txt"), b"stale").unwrap();
+
+        let source = "ORG 100H\n\
+                      MVI C,19\nLXI D,5CH\nCALL 5\n\
+                      MVI C,22\nLXI D,5CH\nCALL 5\n\
+                      LXI H,80H\nMVI M,'x'\n\
+                      MVI C,21\nLXI D,5CH\nCALL 5\n\
+                      MVI C,21\nLXI D,5CH\nCALL 5\n\
+                      MVI C,16\nLXI D,5CH\nCALL 5\n\
+                      MVI C,35\nLXI D,5CH\nCALL 5\n\
+                      LDA 5CH+33\nADI '0'\nMOV E,A\nMVI C,2\nCALL 5\n\
+                      MVI C,17\nLXI D,PATTERN\nCALL 5\n\
+                      LOOP: INR A\nJZ DONE\n\
+                      MVI A,'$'\nSTA 8CH\n\
+                      MVI C,9\nLXI D,81H\nCALL 5\n\
+                      MVI C,18\nCALL 5\nJMP LOOP\n\
+                      DONE: MVI C,0\nCALL 5\n\
+                      PATTERN: DB 0,'????????TXT'\n\
+                      DS 24";
+
+        let output = run(source, "new.txt", b"", directory.clone());
+        let written = fs::read(directory.join("NEW.TXT")).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(output, "2NEW     TXTOLD     TXT");
+        assert_eq!(written.len(), 2 * RECORD_LEN);
+        assert_eq!(written[0], b'x');
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(&parse_fcb("B:FOO*.C"), b"\x02FOO?????C  ");
+        assert_eq!(fcb_name("cpudiag.bin"), Some(*b"CPUDIAG BIN"));
+        assert_eq!(fcb_name("toolongname.txt"), None);
+        assert_eq!(host_name(b"README     "), "README");
+    }
+}
@@ -1,13 +1,20 @@
-use std::{fmt, mem};
+use std::collections::BTreeSet;
+use std::{error, fmt, mem};
 
+use crate::bus::Bus;
 use crate::condition_codes::ConditionCodes;
+use crate::image::Image;
+use crate::interrupt::Interrupt;
 use crate::opcode::Opcode;
 use crate::memory::Memory;
+use crate::observer::{Access, Observer};
 use crate::register::Register;
 use crate::pointer::Pointer;
+use crate::state::{MachineState, Reader, StateError, Writer};
+use crate::trace::{Entry, History};
 
 #[derive(Default)]
-pub struct Cpu {
+pub struct Cpu<B = Memory, O = ()> {
     pub a: Register,
     pub b: Register,
     pub c: Register,
@@ -17,17 +24,64 @@ pub struct Cpu {
     pub l: Register,
     pub sp: Pointer,
     pub pc: Pointer,
-    pub memory: Memory,
+    pub memory: B,
     pub conditions: ConditionCodes,
     pub int_enable: bool,
+    pub halted: bool,
+    pub cycles: u64,
+    pub observer: O,
+    /// Records the instructions run when set, for post-mortem debugging.
+    pub history: Option<History>,
+    /// Addresses at which `emulate` stops with `CpuError::Trap` so the host
+    /// can act, such as by emulating an operating system call.
+    pub traps: BTreeSet<u16>,
+    /// The trap last reported, which the next `emulate` runs through.
+    trapped: Option<u16>,
+    interrupt_request: Option<Interrupt>,
+    ei_delay: bool,
+    operands: [u8; 2],
 }
 
+/// Cycles reported for each call to `Cpu::emulate` while the CPU is halted.
+const HALT_CYCLES: u8 = 4;
+
 pub trait Machine {
     fn input(&mut self, port: u8) -> u8;
     fn output(&mut self, port: u8, byte: u8);
+
+    /// Called when the CPU executes HLT and emits the HLTA status.
+    fn halt_acknowledge(&mut self) {}
 }
 
-impl fmt::Debug for Cpu {
+/// The number of cycles taken by the instruction, or why it couldn't be run.
+pub type StepResult = Result<u8, CpuError>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuError {
+    /// The CPU is halted with interrupts disabled, so only a reset can
+    /// resume it.
+    Halted,
+    UnimplementedOpcode { pc: u16, opcode: Opcode },
+    /// Execution reached an address in `Cpu::traps`. The instruction there
+    /// hasn't run; calling `emulate` again runs it.
+    Trap { pc: u16 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CpuError::Halted => write!(f, "CPU halted"),
+            CpuError::UnimplementedOpcode { pc, opcode } => {
+                write!(f, "Unimplemented INSTRUCTION {:?} at {:04x}", opcode, pc)
+            }
+            CpuError::Trap { pc } => write!(f, "Trap at {:04x}", pc),
+        }
+    }
+}
+
+impl error::Error for CpuError {}
+
+impl<B, O> fmt::Debug for Cpu<B, O> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "{:>4} {:>4} {:>4} {:>4} {:>4} {:>4} {:>4}",
                     "a",   "bc", "de", "hl", "pc", "sp", "flags")?;
@@ -45,25 +99,113 @@ impl fmt::Debug for Cpu {
     }
 }
 
-// HELP GROUP
 impl Cpu {
-
     pub fn new() -> Self {
         Self::default()
     }
+}
+
+impl<B: Bus> Cpu<B> {
+    pub fn with_bus(memory: B) -> Self {
+        Self::with_observer(memory, ())
+    }
+}
+
+// HELP GROUP
+impl<B: Bus, O: Observer> Cpu<B, O> {
+
+    pub fn with_observer(memory: B, observer: O) -> Self {
+        Cpu {
+            a: Register::default(),
+            b: Register::default(),
+            c: Register::default(),
+            d: Register::default(),
+            e: Register::default(),
+            h: Register::default(),
+            l: Register::default(),
+            sp: Pointer::default(),
+            pc: Pointer::default(),
+            memory,
+            conditions: ConditionCodes::default(),
+            int_enable: false,
+            halted: false,
+            cycles: 0,
+            observer,
+            history: None,
+            traps: BTreeSet::new(),
+            trapped: None,
+            interrupt_request: None,
+            ei_delay: false,
+            operands: [0; 2],
+        }
+    }
 
     pub fn load_into_rom(&mut self, memory: &[u8], position: u16) {
         self.memory.load(memory, position);
     }
 
-    pub fn emulate<M: Machine>(&mut self, machine: &mut M) -> u8 {
+    /// Loads every segment of `image` and jumps to its start address, if it
+    /// has one.
+    pub fn load_image(&mut self, image: &Image) {
+        image.load(&mut self.memory);
+
+        if let Some(start) = image.start {
+            self.pc = start.into();
+        }
+    }
+
+    pub fn emulate<M: Machine>(&mut self, machine: &mut M) -> StepResult {
+        // Interrupts are sampled between instructions, except directly after
+        // EI so that the instruction following it always runs first.
+        let ei_delay = mem::replace(&mut self.ei_delay, false);
+        if self.int_enable && !ei_delay {
+            if let Some(instruction) = self.interrupt_request.take() {
+                // The instruction comes from the data bus, so PC isn't
+                // advanced and a CALL or RST pushes the interrupted address.
+                self.int_enable = false;
+                self.halted = false;
+                self.operands = instruction.operands();
+                self.remember(instruction.opcode(), true);
+                self.observer.on_interrupt(*self.pc, instruction);
+                return self.execute(instruction.opcode(), machine);
+            }
+        }
+
+        if self.halted {
+            return if self.int_enable {
+                self.cycles += HALT_CYCLES as u64;
+                Ok(HALT_CYCLES)
+            } else {
+                Err(CpuError::Halted)
+            };
+        }
+
         let pc = self.pc;
-        let opcode = Opcode::from(self.memory[pc]);
-        let mut jumped = false;
 
+        if self.trapped.take() != Some(*pc) && self.traps.contains(&*pc) {
+            self.trapped = Some(*pc);
+            return Err(CpuError::Trap { pc: *pc });
+        }
+
+        let opcode = Opcode::from(self.memory.read(*pc));
+        let size = opcode.size() as u16;
+
+        for i in 1..size {
+            self.operands[i as usize - 1] = self.memory.read(*(pc + i));
+        }
+
+        self.remember(opcode, false);
+        self.observer.on_fetch(*pc, opcode);
+
+        self.pc += size;
+        self.execute(opcode, machine)
+    }
+
+    fn execute<M: Machine>(&mut self, opcode: Opcode, machine: &mut M) -> StepResult {
+        let mut jumped = false;
 
         match *opcode {
-            0x00 | 0x08 | 0x20 | 0x28 | 0x30 | 0x38 => {}
+            0x00 | 0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => {}
             0x01 | 0x11 | 0x21 | 0x31 => self.lxi(*opcode),
 
             0x02 | 0x12 => self.stax(*opcode),
@@ -103,7 +245,10 @@ impl Cpu {
 
             0x40..=0x75 | 0x77..=0x7f => self.mov(*opcode),
 
-            0x76 => ::std::process::exit(0),
+            0x76 => {
+                self.halted = true;
+                machine.halt_acknowledge();
+            }
 
             0x80..=0x87 => self.add(*opcode),
             0x88..=0x8f => self.adc(*opcode),
@@ -122,7 +267,7 @@ impl Cpu {
             0xc1 | 0xd1 | 0xe1 | 0xf1 => self.pop(*opcode),
 
             0xc2 => jumped = self.jnz(),
-            0xc3 => jumped = self.jmp(),
+            0xc3 | 0xcb => jumped = self.jmp(),
             0xc4 => jumped = self.cnz(),
 
             0xc5 | 0xd5 | 0xe5 | 0xf5 => self.push(*opcode),
@@ -142,12 +287,19 @@ impl Cpu {
 
             0xd0 => jumped = self.rnc(),
             0xd2 => jumped = self.jnc(),
-            0xd3 => machine.output(self.get_d8(), *self.a),
+            0xd3 => {
+                self.observer.on_io(self.get_d8(), *self.a, Access::Write);
+                machine.output(self.get_d8(), *self.a);
+            }
             0xd4 => jumped = self.cnc(),
             0xd6 => self.sui(),
             0xd8 => jumped = self.rc(),
             0xda => jumped = self.jc(),
-            0xdb => self.a = machine.input(self.get_d8()).into(),
+            0xdb => {
+                let value = machine.input(self.get_d8());
+                self.observer.on_io(self.get_d8(), value, Access::Read);
+                self.a = value.into();
+            }
             0xdc => jumped = self.cc(),
             0xde => self.sbi(),
 
@@ -174,52 +326,66 @@ impl Cpu {
             0xfb => self.ei(),
             0xfc => jumped = self.cm(),
             0xfe => self.cpi(),
+        }
 
-            code => {
-                panic!("Unimplemented INSTRUCTION {:?}", Opcode::from(code));
-            }
+        let cycles = opcode.cycles(jumped);
+        self.cycles += cycles as u64;
+        Ok(cycles)
+    }
 
+    fn remember(&mut self, opcode: Opcode, interrupt: bool) {
+        if let Some(mut history) = self.history.take() {
+            let bytes = [*opcode, self.operands[0], self.operands[1]];
+            history.push(Entry::new(self, bytes, interrupt));
+            self.history = Some(history);
         }
+    }
 
-        if !jumped {
-            self.pc += opcode.size() as u16;
-        }
+    fn read<A: Into<u16>>(&mut self, address: A) -> u8 {
+        let address = address.into();
+        let value = self.memory.read(address);
+        self.observer.on_mem_read(address, value);
+        value
+    }
 
-        opcode.cycle_size()
+    fn write<A: Into<u16>, V: Into<u8>>(&mut self, address: A, value: V) {
+        let (address, value) = (address.into(), value.into());
+        self.observer.on_mem_write(address, value);
+        self.memory.write(address, value);
     }
 
-    fn get_offset(&self) -> u8 {
+    fn get_offset(&mut self) -> u8 {
         let offset = ((self.h.to_u16()) << 8) | self.l.to_u16();
-        self.memory[offset]
+        self.read(offset)
     }
 
     fn set_offset<I: Into<u8>>(&mut self, value: I) {
         let offset = ((self.h.to_u16()) << 8) | self.l.to_u16();
-        self.memory.write(offset, value);
+        self.write(offset, value);
     }
 
     fn get_d8(&self) -> u8 {
-        self.memory[self.pc + 1]
+        self.operands[0]
     }
 
     fn get_d16(&self) -> u16 {
-        (self.memory[self.pc + 2] as u16) << 8 | self.memory[self.pc + 1] as u16
+        (self.operands[1] as u16) << 8 | self.operands[0] as u16
     }
 
     pub fn print_opcode(&self) {
-        let pc = self.pc;
-        let opcode = Opcode::from(self.memory[pc]);
+        let pc = *self.pc;
+        let opcode = Opcode::from(self.memory.peek(pc));
 
         if opcode.size() == 1 {
-            println!("{:04x} {:?}", *pc, opcode);
+            println!("{:04x} {:?}", pc, opcode);
         } else if opcode.size() == 2 {
-            println!("{:04x} {:?} {:02x}", *pc, opcode, self.memory[pc+1]);
+            println!("{:04x} {:?} {:02x}", pc, opcode, self.memory.peek(pc.wrapping_add(1)));
         } else {
             println!("{:04x} {:?} {:02x}{:02x}",
-                     *pc,
+                     pc,
                      opcode,
-                     self.memory[pc+2],
-                     self.memory[pc+1]);
+                     self.memory.peek(pc.wrapping_add(2)),
+                     self.memory.peek(pc.wrapping_add(1)));
         }
 
     }
@@ -227,7 +393,7 @@ impl Cpu {
 }
 
 // DATA TRANSFER GROUP
-impl Cpu {
+impl<B: Bus, O: Observer> Cpu<B, O> {
 
     fn mov(&mut self, code: u8) {
         macro_rules! mov {
@@ -340,14 +506,14 @@ impl Cpu {
             (self.d.to_u16()) << 8 | self.e.to_u16()
         };
 
-        self.a = self.memory[offset].into();
+        self.a = self.read(offset).into();
     }
 
     fn stax(&mut self, code: u8) {
         macro_rules! stax {
             ($x:ident $y:ident) => {{
                 let x = (self.$x.to_u16() << 8) | self.$y.to_u16();
-                self.memory.write(x, self.a);
+                self.write(x, self.a);
             }}
         }
         match code {
@@ -367,10 +533,8 @@ impl Cpu {
     }
 
     fn xri(&mut self) {
-        let rhs = self.get_d8();
-        let lhs = self.a;
-        self.a = (*lhs ^ rhs).into();
-        self.conditions.set_all(self.a.to_u16(), *self.a);
+        self.a = (*self.a ^ self.get_d8()).into();
+        self.conditions.set_logical(*self.a, false);
     }
 
     fn xchg(&mut self) {
@@ -379,8 +543,8 @@ impl Cpu {
     }
 
     fn xthl(&mut self) {
-        let new_h = self.memory[self.sp + 1];
-        let new_l = self.memory[self.sp];
+        let new_h = self.read(self.sp + 1);
+        let new_l = self.read(self.sp);
 
         let old_h = *self.h;
         let old_l = *self.l;
@@ -388,8 +552,8 @@ impl Cpu {
         self.h = new_h.into();
         self.l = new_l.into();
 
-        self.memory.write(self.sp + 1, old_h);
-        self.memory.write(self.sp, old_l);
+        self.write(self.sp + 1, old_h);
+        self.write(self.sp, old_l);
     }
 }
 
@@ -418,13 +582,6 @@ macro_rules! get_adrs {
     }
 }
 
-macro_rules! update_address {
-    ($this:ident.$addr:ident $operand:tt $value:expr) => {{
-        $this.$addr $operand $value;
-        $this.conditions.set_all_except_carry($this.$addr.to_u16(), *$this.$addr);
-    }}
-}
-
 macro_rules! update_pair {
     ($this:ident $x:ident $op:tt $y:ident) => {{
         let mut answer = (($this.$x.to_u16()) << 8) | $this.$y.to_u16();
@@ -436,130 +593,116 @@ macro_rules! update_pair {
 }
 
 // ARITHMETIC GROUP
-impl Cpu {
+impl<B: Bus, O: Observer> Cpu<B, O> {
 
-    fn add(&mut self, code: u8) {
-        let rhs = get_adrs!(self, code, 0x80, 0x81, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87);
-        let lhs = self.a;
-        let answer = lhs.to_u16() + rhs.to_u16();
+    /// Adds `rhs` and the carry in to A.
+    fn add_to_a(&mut self, rhs: u8, carry: bool) {
+        let lhs = *self.a;
+        let answer = lhs as u16 + rhs as u16 + carry as u16;
 
+        self.conditions.set_all(answer, lhs, rhs, carry);
         self.a = answer.into();
-
-        self.conditions.set_all(answer, (*lhs & 0xf) + (*rhs & 0xf));
     }
 
-    fn adc(&mut self, code: u8) {
-        let lhs = self.a.to_u16();
-        let addr = get_adrs!(self, code, 0x88, 0x89, 0x8a, 0x8b, 0x8c, 0x8d, 0x8e, 0x8f);
-        let rhs = addr.to_u16() + self.conditions.cy as u16;
+    /// Subtracts `rhs` and the borrow in from A, returning the difference
+    /// without storing it. The ALU adds the complement of `rhs` with the
+    /// borrow inverted as the carry in, and CY is the inverted carry out.
+    fn subtract(&mut self, rhs: u8, borrow: bool) -> u8 {
+        let lhs = *self.a;
+        let answer = lhs as u16 + !rhs as u16 + !borrow as u16;
 
-        let answer = lhs.wrapping_add(rhs);
+        self.conditions.set_all(answer, lhs, !rhs, !borrow);
+        self.conditions.cy = !self.conditions.cy;
+        answer as u8
+    }
 
-        self.a = answer.into();
-        self.conditions.set_all(answer, (lhs as u8 & 0xf).wrapping_add(rhs as u8 & 0xf));
+    fn add(&mut self, code: u8) {
+        let rhs = get_adrs!(self, code, 0x80, 0x81, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87);
+        self.add_to_a(*rhs, false);
     }
 
-    fn adi(&mut self) {
-        let lhs = self.a.to_u16();
-        let rhs = self.get_d8() as u16;
-        let answer = lhs.wrapping_add(rhs);
+    fn adc(&mut self, code: u8) {
+        let rhs = get_adrs!(self, code, 0x88, 0x89, 0x8a, 0x8b, 0x8c, 0x8d, 0x8e, 0x8f);
+        self.add_to_a(*rhs, self.conditions.cy);
+    }
 
-        self.conditions.set_all(answer, (lhs as u8 & 0xf).wrapping_add(rhs as u8 & 0xf));
-        self.a = answer.into();
+    fn adi(&mut self) {
+        self.add_to_a(self.get_d8(), false);
     }
 
     fn aci(&mut self) {
-        let lhs = self.a.to_u16();
-        let rhs = self.get_d8().wrapping_add(self.conditions.cy as u8) as u16;
-        let answer = lhs.wrapping_add(rhs);
-
-        self.conditions.set_all(answer, (lhs as u8 & 0xf).wrapping_add(rhs as u8 & 0xf));
-        self.a = answer.into();
-
+        self.add_to_a(self.get_d8(), self.conditions.cy);
     }
 
     fn cmp(&mut self, code: u8) {
-        let lhs = self.a.to_u16();
         let rhs = get_adrs!(self, code, 0xb8, 0xb9, 0xba, 0xbb, 0xbc, 0xbd, 0xbe, 0xbf);
-
-        let answer = lhs.wrapping_sub(rhs.to_u16());
-
-        self.conditions.set_all(answer, (lhs & 0xf).wrapping_sub(rhs.to_u16() & 0xf) as u8);
+        self.subtract(*rhs, false);
     }
 
     fn sub(&mut self, code: u8) {
-        let lhs = self.a.to_u16();
         let rhs = get_adrs!(self, code, 0x90, 0x91, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97);
-        let answer = lhs.wrapping_sub(rhs.to_u16());
-
-        self.a = answer.into();
-        self.conditions.set_all(answer, (lhs & 0xf).wrapping_sub(rhs.to_u16() & 0xf) as u8);
+        self.a = self.subtract(*rhs, false).into();
     }
 
     fn sbb(&mut self, code: u8) {
-        let lhs = self.a.to_u16();
-        let addr = get_adrs!(self, code, 0x98, 0x99, 0x9a, 0x9b, 0x9c, 0x9d, 0x9e, 0x9f);
-        let rhs = addr.to_u16().wrapping_add(self.conditions.cy as u16);
-
-        let answer = lhs.wrapping_sub(rhs);
-        self.a = answer.into();
-        self.conditions.set_all(answer, (lhs & 0xf).wrapping_sub(rhs & 0xf) as u8);
+        let rhs = get_adrs!(self, code, 0x98, 0x99, 0x9a, 0x9b, 0x9c, 0x9d, 0x9e, 0x9f);
+        self.a = self.subtract(*rhs, self.conditions.cy).into();
     }
 
     fn sui(&mut self) {
-        let lhs = self.a.to_u16();
-        let rhs = self.get_d8() as u16;
-        let answer = lhs.wrapping_sub(rhs);
-        self.conditions.set_all(answer, (lhs & 0xf).wrapping_sub(rhs & 0xf) as u8);
-        self.a = answer.into();
+        self.a = self.subtract(self.get_d8(), false).into();
     }
 
     fn sbi(&mut self) {
-        let lhs = self.a.to_u16();
-
-        let rhs = self.get_d8().wrapping_add(self.conditions.cy as u8) as u16;
+        self.a = self.subtract(self.get_d8(), self.conditions.cy).into();
+    }
 
-        let answer = self.a.to_u16().wrapping_sub(rhs);
+    /// INR sets AC on a carry out of bit 3, like adding one.
+    fn increment(&mut self, value: u8) -> u8 {
+        let answer = value.wrapping_add(1);
+        self.conditions.set_all_except_carry(answer as u16, value, 1, false);
+        answer
+    }
 
-        self.conditions.set_all(answer, (lhs & 0xf).wrapping_sub(rhs & 0xf) as u8);
-        self.a = answer.into();
+    /// DCR adds 0xff, so AC is set unless the low nibble was zero.
+    fn decrement(&mut self, value: u8) -> u8 {
+        let answer = value.wrapping_sub(1);
+        self.conditions.set_all_except_carry(answer as u16, value, 0xff, false);
+        answer
     }
 
     fn inr(&mut self, code: u8) {
-
         match code {
-            0x04 => update_address!(self.b += 1),
-            0x0c => update_address!(self.c += 1),
-            0x14 => update_address!(self.d += 1),
-            0x1c => update_address!(self.e += 1),
-            0x24 => update_address!(self.h += 1),
-            0x2c => update_address!(self.l += 1),
+            0x04 => self.b = self.increment(*self.b).into(),
+            0x0c => self.c = self.increment(*self.c).into(),
+            0x14 => self.d = self.increment(*self.d).into(),
+            0x1c => self.e = self.increment(*self.e).into(),
+            0x24 => self.h = self.increment(*self.h).into(),
+            0x2c => self.l = self.increment(*self.l).into(),
             0x34 => {
-                let lhs = self.get_offset();
-                let value = lhs.wrapping_add(1);
-                self.conditions.set_all_except_carry(value as u16, (lhs & 0xf) + 1);
+                let value = self.get_offset();
+                let value = self.increment(value);
                 self.set_offset(value);
             },
-            0x3c => update_address!(self.a += 1),
+            0x3c => self.a = self.increment(*self.a).into(),
             _ => unreachable!(),
         };
     }
 
     fn dcr(&mut self, code: u8) {
-
         match code {
-            0x05 => update_address!(self.b -= 1),
-            0x0d => update_address!(self.c -= 1),
-            0x15 => update_address!(self.d -= 1),
-            0x1d => update_address!(self.e -= 1),
-            0x25 => update_address!(self.h -= 1),
-            0x2d => update_address!(self.l -= 1),
+            0x05 => self.b = self.decrement(*self.b).into(),
+            0x0d => self.c = self.decrement(*self.c).into(),
+            0x15 => self.d = self.decrement(*self.d).into(),
+            0x1d => self.e = self.decrement(*self.e).into(),
+            0x25 => self.h = self.decrement(*self.h).into(),
+            0x2d => self.l = self.decrement(*self.l).into(),
             0x35 => {
-                let value = self.get_offset().wrapping_sub(1);
-                self.conditions.set_all_except_carry(value as u16, value);
+                let value = self.get_offset();
+                let value = self.decrement(value);
                 self.set_offset(value);
             },
-            0x3d => update_address!(self.a -= 1),
+            0x3d => self.a = self.decrement(*self.a).into(),
             _ => unreachable!(),
         };
     }
@@ -585,70 +728,50 @@ impl Cpu {
     }
 
     fn dad(&mut self, code: u8) {
-        macro_rules! dad {
-            ($x:ident, $y:ident) => {{
-                let hl = ((self.h.to_u16()) << 8) | self.l.to_u16();
-                let second = (self.$x.to_u16()) << 8 | self.$y.to_u16();
-
-                let answer = hl.wrapping_add(second);
+        let hl = (self.h.to_u16() << 8) | self.l.to_u16();
+        let second = match code {
+            0x09 => (self.b.to_u16() << 8) | self.c.to_u16(),
+            0x19 => (self.d.to_u16() << 8) | self.e.to_u16(),
+            0x29 => hl,
+            0x39 => *self.sp,
+            _ => unreachable!(),
+        };
 
-                self.conditions.set_cy(answer);
-                self.h = (answer >> 8).into();
-                self.l = answer.into();
-            }}
-        }
+        // The carry is out of the full 16-bit sum.
+        let answer = u32::from(hl) + u32::from(second);
 
-        match code {
-            0x09 => dad!(b, c),
-            0x19 => dad!(d, e),
-            0x29 => dad!(h, l),
-            0x39 => {
-                let mut hl = (self.h.to_u16() << 8) | self.l.to_u16();
-                hl = hl.wrapping_add(*self.sp);
-
-                self.conditions.set_cy(hl);
-                self.h = (hl >> 8).into();
-                self.l = hl.into();
-            },
-            _ => unreachable!(),
-        }
+        self.conditions.cy = answer > 0xffff;
+        self.h = ((answer >> 8) as u16).into();
+        self.l = (answer as u16).into();
     }
 
+    /// Adds 6 to each digit of A that is over 9 or carried out of, so that
+    /// A holds the BCD sum after adding two BCD numbers. CY is set if the
+    /// high digit needed correcting, and otherwise left alone.
     fn daa(&mut self) {
-        let mut answer = self.a.to_u16();
-
-        let least = answer & 0xf;
+        let least = *self.a & 0xf;
+        let most = *self.a >> 4;
+        let mut correction = 0;
+        let mut carry = self.conditions.cy;
 
         if self.conditions.ac || least > 9 {
-            answer += 6;
-
-            if answer & 0xf < least {
-                self.conditions.ac = true;
-            }
+            correction |= 0x06;
         }
 
-        let least = answer & 0xf;
-        let mut most = (answer >> 4) & 0xf;
-
-        if self.conditions.cy || most > 9 {
-            most += 6;
+        if carry || most > 9 || (most >= 9 && least > 9) {
+            correction |= 0x60;
+            carry = true;
         }
 
-        let answer = ((most << 4) as u16) | least as u16;
-        self.conditions.set_all_except_ac(answer);
-
-        self.a = answer.into();
+        self.add_to_a(correction, false);
+        self.conditions.cy = carry;
     }
 }
 
 
 // BRANCH GROUP
-impl Cpu {
+impl<B: Bus, O: Observer> Cpu<B, O> {
     fn jmp(&mut self) -> bool {
-        if cfg!(feature = "cpudiag") && self.get_d16() == 0 {
-            println!();
-            ::std::process::exit(0);
-        }
         self.jump();
         true
     }
@@ -714,22 +837,12 @@ impl Cpu {
     }
 
     fn call(&mut self) -> bool {
-        use std::char;
-
-        if cfg!(feature = "cpudiag") && self.get_d16() == 5 && self.c == 2 {
-            let letter = (self.d.to_u16() << 8) | self.e.to_u16();
-            print!("{}", char::from_u32(letter as u32).unwrap());
-            false
-        } else if cfg!(feature = "cpudiag") && self.get_d16() == 0 {
-            ::std::process::exit(0)
-        } else {
-            let ret = *self.pc + 3;
-            self.memory.write(self.sp - 1, (ret >> 8) as u8);
-            self.memory.write(self.sp - 2, ret as u8);
-            self.sp -= 2;
-            self.jump();
-            true
-        }
+        let ret = *self.pc;
+        self.write(self.sp - 1, (ret >> 8) as u8);
+        self.write(self.sp - 2, ret as u8);
+        self.sp -= 2;
+        self.jump();
+        true
     }
 
     fn cz(&mut self) -> bool {
@@ -863,8 +976,8 @@ impl Cpu {
     }
 
     fn ret(&mut self) -> bool {
-        let low = self.memory[self.sp] as u16;
-        let high = self.memory[self.sp + 1] as u16;
+        let low = self.read(self.sp) as u16;
+        let high = self.read(self.sp + 1) as u16;
 
         self.pc = ((high << 8) | low).into();
         self.sp += 2;
@@ -873,20 +986,20 @@ impl Cpu {
 }
 
 // LOGICAL GROUP
-impl Cpu {
+impl<B: Bus, O: Observer> Cpu<B, O> {
     fn ani(&mut self) {
-        let answer = (*self.a & self.get_d8()) as u16;
-
-        self.conditions.set_cy(answer);
-        self.conditions.set_z(answer);
-        self.conditions.set_s(answer);
-        self.conditions.set_p(answer);
+        let rhs = self.get_d8();
+        self.and_with_a(rhs);
+    }
 
-        self.a = answer.into();
+    /// ANA and ANI set AC from the OR of bit 3 of their operands.
+    fn and_with_a(&mut self, rhs: u8) {
+        let lhs = *self.a;
+        self.a = (lhs & rhs).into();
+        self.conditions.set_logical(*self.a, (lhs | rhs) & 0x08 != 0);
     }
 
     fn ana(&mut self, code: u8) {
-        let lhs = self.a;
         let rhs = match code {
             0xa0 => self.b,
             0xa1 => self.c,
@@ -899,11 +1012,7 @@ impl Cpu {
             _ => unreachable!(),
         };
 
-        let answer = lhs & rhs;
-
-        self.a = answer;
-
-        self.conditions.set_all(answer.to_u16(), *answer);
+        self.and_with_a(*rhs);
     }
 
     fn rlc(&mut self) {
@@ -935,7 +1044,7 @@ impl Cpu {
     }
 
     fn ora(&mut self, code: u8) {
-        self.a |= match code {
+        let rhs = match code {
             0xb0 => self.b,
             0xb1 => self.c,
             0xb2 => self.d,
@@ -947,18 +1056,13 @@ impl Cpu {
             _ => unreachable!(),
         };
 
-        self.conditions.set_all(self.a.to_u16(), 0);
+        self.a |= rhs;
+        self.conditions.set_logical(*self.a, false);
     }
 
     fn ori(&mut self) {
-        let answer = self.a.to_u16() | self.get_d8() as u16;
-
-        self.conditions.set_cy(answer);
-        self.conditions.set_z(answer);
-        self.conditions.set_s(answer);
-        self.conditions.set_p(answer);
-
-        self.a = answer.into();
+        self.a = (*self.a | self.get_d8()).into();
+        self.conditions.set_logical(*self.a, false);
     }
 
     fn stc(&mut self) {
@@ -976,35 +1080,29 @@ impl Cpu {
     fn shld(&mut self) {
         let addr = self.get_d16();
 
-        self.memory.write(addr, self.l);
-        self.memory.write(addr + 1, *self.h);
+        self.write(addr, self.l);
+        self.write(addr.wrapping_add(1), *self.h);
     }
 
     fn lhld(&mut self) {
         let addr = self.get_d16();
 
-        self.l = self.memory[addr].into();
-        self.h = self.memory[addr + 1].into();
+        self.l = self.read(addr).into();
+        self.h = self.read(addr.wrapping_add(1)).into();
     }
 
     fn lda(&mut self) {
-        self.a = self.memory[self.get_d16()].into();
+        let adr = self.get_d16();
+        self.a = self.read(adr).into();
     }
 
     fn sta(&mut self) {
         let adr = self.get_d16();
-        self.memory.write(adr, self.a);
+        self.write(adr, self.a);
     }
 
     fn cpi(&mut self) {
-        let byte = self.get_d8() as u16;
-        let answer = self.a.to_u16().wrapping_sub(byte);
-
-        self.conditions.set_z(answer);
-        self.conditions.cy = self.a.to_u16() < byte;
-        self.conditions.set_p(answer);
-        self.conditions.set_s(answer);
-        self.conditions.set_ac((self.a & 0xf).wrapping_sub(byte as u8 & 0xf));
+        self.subtract(self.get_d8(), false);
     }
 
     fn xra(&mut self, code: u8) {
@@ -1020,35 +1118,46 @@ impl Cpu {
             _ => unreachable!(),
         };
 
-        let lhs = self.a;
-        self.a = (*lhs ^ *rhs).into();
-
-        self.conditions.set_all(self.a.to_u16(), (*lhs & 0xf) ^ (*rhs & 0xf));
+        self.a = (*self.a ^ *rhs).into();
+        self.conditions.set_logical(*self.a, false);
     }
 }
 
 // IO GROUP
-impl Cpu {
+impl<B: Bus, O: Observer> Cpu<B, O> {
 
     fn rst(&mut self, code: u8) -> bool {
         let ret = self.pc;
-        self.memory.write(self.sp - 1, ret >> 8);
-        self.memory.write(self.sp - 2, ret);
+        self.write(self.sp - 1, ret >> 8);
+        self.write(self.sp - 2, ret);
         self.sp -= 2;
         self.pc = (code & 0x38).into();
         true
     }
 
-    pub fn interrupt(&mut self, code: u8) {
-        self.rst(code);
-        self.int_enable = false;
+    /// Raises the interrupt request line with the instruction the device will
+    /// place on the bus. The request is held until the CPU accepts it at an
+    /// instruction boundary with interrupts enabled; a newer request replaces
+    /// an older one.
+    pub fn request_interrupt<I: Into<Interrupt>>(&mut self, instruction: I) {
+        self.interrupt_request = Some(instruction.into());
+    }
+
+    pub fn interrupt_pending(&self) -> bool {
+        self.interrupt_request.is_some()
+    }
+
+    /// The instruction passed to `request_interrupt` that hasn't been
+    /// accepted yet.
+    pub fn pending_interrupt(&self) -> Option<Interrupt> {
+        self.interrupt_request
     }
 
     fn push(&mut self, code: u8) {
         macro_rules! push {
             ($x:ident $y:ident) => {{
-                self.memory.write(self.sp - 1, self.$x);
-                self.memory.write(self.sp - 2, self.$y);
+                self.write(self.sp - 1, self.$x);
+                self.write(self.sp - 2, self.$y);
                 self.sp -= 2;
             }}
         }
@@ -1064,8 +1173,8 @@ impl Cpu {
     fn pop(&mut self, code: u8) {
         macro_rules! pop {
             ($x:ident $y:ident) => {{
-                self.$y = self.memory[self.sp].into();
-                self.$x = self.memory[self.sp + 1].into();
+                self.$y = self.read(self.sp).into();
+                self.$x = self.read(self.sp + 1).into();
                 self.sp += 2;
             }}
         }
@@ -1080,6 +1189,7 @@ impl Cpu {
 
     fn ei(&mut self) {
         self.int_enable = true;
+        self.ei_delay = true;
     }
 
     fn di(&mut self) {
@@ -1087,9 +1197,101 @@ impl Cpu {
     }
 }
 
+// SAVE STATE
+impl<B: Bus, O: Observer> Cpu<B, O> {
+
+    /// Serializes the registers, flags, interrupt, halt and trap state, cycle
+    /// count and all 64K of memory as seen through the bus, followed by `machine`'s
+    /// state. The memory map itself isn't saved.
+    pub fn save_state<M: MachineState>(&self, machine: &M) -> Vec<u8> {
+        let mut writer = Writer::default();
+
+        for register in [self.a, self.b, self.c, self.d, self.e, self.h, self.l] {
+            writer.u8(*register);
+        }
+
+        writer.u16(*self.sp);
+        writer.u16(*self.pc);
+        writer.u8(self.conditions.into());
+        writer.bool(self.int_enable);
+        writer.bool(self.halted);
+        writer.bool(self.ei_delay);
+        writer.bool(self.interrupt_request.is_some());
+        writer.bytes(&self.interrupt_request.map_or([0; 3], |request| {
+            let mut bytes = [0; 3];
+            bytes[..request.bytes().len()].copy_from_slice(request.bytes());
+            bytes
+        }));
+        writer.u64(self.cycles);
+        writer.bool(self.trapped.is_some());
+        writer.u16(self.trapped.unwrap_or(0));
+
+        for address in 0..=0xffff {
+            writer.u8(self.memory.peek(address));
+        }
+
+        machine.save_state(&mut writer);
+        writer.finish()
+    }
+
+    /// Restores a state written by `save_state`. Memory is written with
+    /// `Bus::load`, so ROM is restored too. Nothing changes if the state
+    /// can't be restored.
+    pub fn load_state<M: MachineState>(&mut self, machine: &mut M, state: &[u8])
+        -> Result<(), StateError>
+    {
+        let mut reader = Reader::new(state)?;
+        let registers = reader.bytes(7)?;
+        let sp = reader.u16()?;
+        let pc = reader.u16()?;
+        let conditions = reader.u8()?;
+        let int_enable = reader.bool()?;
+        let halted = reader.bool()?;
+        let ei_delay = reader.bool()?;
+        let requested = reader.bool()?;
+        let request: [u8; 3] = reader.bytes(3)?.try_into().unwrap();
+        let cycles = reader.u64()?;
+        let trapped = reader.bool()?;
+        let trap = reader.u16()?;
+        let memory = reader.bytes(0x10000)?;
+
+        // The machine restores itself as it reads, so it is put back as it was
+        // if its part of the state turns out to be damaged.
+        let mut backup = Writer::default();
+        machine.save_state(&mut backup);
+
+        if let Err(error) = machine.load_state(&mut reader).and_then(|_| reader.finish()) {
+            let backup = backup.finish();
+            machine.load_state(&mut Reader::new(&backup)?)?;
+            return Err(error);
+        }
+
+        self.a = registers[0].into();
+        self.b = registers[1].into();
+        self.c = registers[2].into();
+        self.d = registers[3].into();
+        self.e = registers[4].into();
+        self.h = registers[5].into();
+        self.l = registers[6].into();
+        self.sp = sp.into();
+        self.pc = pc.into();
+        self.conditions = conditions.into();
+        self.int_enable = int_enable;
+        self.halted = halted;
+        self.ei_delay = ei_delay;
+        self.interrupt_request = requested.then(|| request.into());
+        self.cycles = cycles;
+        self.trapped = trapped.then_some(trap);
+        self.memory.load(memory, 0);
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::asm;
+
     struct Facade;
 
     impl Machine for Facade {
@@ -1098,17 +1300,22 @@ mod tests {
         fn output(&mut self, _: u8, _: u8) {}
     }
 
+    fn assemble<B: Bus, O: Observer>(cpu: &mut Cpu<B, O>, source: &str) {
+        let program = asm::assemble(source).unwrap();
+        cpu.load_into_rom(&program.bytes, program.origin);
+    }
+
     #[test]
     fn cpi() {
         let mut cpu = Cpu::new();
 
-        cpu.load_into_rom(&[0xfe, 0x40], 0);
+        assemble(&mut cpu, "CPI 40H");
 
         cpu.a = 0x4au8.into();
         cpu.conditions.z = true;
         cpu.conditions.cy = true;
 
-        cpu.emulate(&mut Facade);
+        cpu.emulate(&mut Facade).unwrap();
 
         assert!(!cpu.conditions.z);
         assert!(!cpu.conditions.cy);
@@ -1119,19 +1326,168 @@ mod tests {
         let mut cpu = Cpu::new();
 
         cpu.a = 0xf2u8.into();
-        cpu.load_into_rom(&[0x0f], 0);
-        cpu.emulate(&mut Facade);
+        assemble(&mut cpu, "RRC");
+        cpu.emulate(&mut Facade).unwrap();
 
         assert_eq!(cpu.a, 0x79);
     }
 
+    /// Adds the way the 8080's ALU does, one bit at a time, returning the sum
+    /// and the carries out of bits 3 and 7.
+    fn ripple(lhs: u8, rhs: u8, mut carry: bool) -> (u8, bool, bool) {
+        let mut sum = 0;
+        let mut half = false;
+
+        for bit in 0..8 {
+            let (x, y) = (lhs >> bit & 1 != 0, rhs >> bit & 1 != 0);
+            sum |= ((x ^ y ^ carry) as u8) << bit;
+            carry = (x && y) || (carry && (x ^ y));
+
+            if bit == 3 {
+                half = carry;
+            }
+        }
+
+        (sum, half, carry)
+    }
+
+    fn flag_word(answer: u8, ac: bool, cy: bool) -> u8 {
+        let parity = (0..8).filter(|bit| answer >> bit & 1 != 0).count() % 2 == 0;
+
+        answer & 0x80 | ((answer == 0) as u8) << 6 | (ac as u8) << 4 | (parity as u8) << 2
+            | 0x02 | cy as u8
+    }
+
+    /// The A register and flag word the 8080 leaves after the ALU operation
+    /// at `opcode`, which is one of ADD B to CMP B.
+    fn reference(opcode: u8, a: u8, b: u8, cy: bool) -> (u8, u8) {
+        let subtract = |borrow: bool| {
+            let (answer, ac, carry) = ripple(a, !b, !borrow);
+            (answer, ac, !carry)
+        };
+
+        let (answer, ac, cy) = match opcode & 0xf8 {
+            0x80 => ripple(a, b, false),
+            0x88 => ripple(a, b, cy),
+            0x90 | 0xb8 => subtract(false),
+            0x98 => subtract(cy),
+            0xa0 => (a & b, (a | b) & 0x08 != 0, false),
+            0xa8 => (a ^ b, false, false),
+            0xb0 => (a | b, false, false),
+            _ => unreachable!(),
+        };
+
+        (if opcode & 0xf8 == 0xb8 { a } else { answer }, flag_word(answer, ac, cy))
+    }
+
+    #[test]
+    fn alu_flags_match_reference() {
+        let mut cpu = Cpu::new();
+
+        for opcode in (0x80..0xc0).step_by(8) {
+            // The immediate form of each operation, such as ADI for ADD.
+            let immediate = opcode - 0x80 + 0xc6;
+
+            for (a, b) in (0..=0xff).flat_map(|a| (0..=0xff).map(move |b| (a, b))) {
+                for cy in [false, true] {
+                    let expected = reference(opcode, a, b, cy);
+
+                    for program in [[opcode, 0], [immediate, b]] {
+                        cpu.load_into_rom(&program, 0);
+                        cpu.pc = 0u16.into();
+                        cpu.a = a.into();
+                        cpu.b = b.into();
+                        cpu.conditions = ConditionCodes::default();
+                        cpu.conditions.cy = cy;
+                        cpu.emulate(&mut Facade).unwrap();
+
+                        assert_eq!((*cpu.a, u8::from(cpu.conditions)), expected,
+                                   "{:?} with A={:02x} B={:02x} CY={}",
+                                   Opcode::from(program[0]), a, b, cy);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn inr_and_dcr_flags_match_reference() {
+        let mut cpu = Cpu::new();
+        cpu.load_into_rom(&[0x04, 0x05], 0);
+
+        for (value, cy) in (0..=0xff).flat_map(|value| [(value, false), (value, true)]) {
+            for (pc, rhs) in [(0u16, 1), (1, 0xff)] {
+                let (answer, ac, _) = ripple(value, rhs, false);
+
+                cpu.pc = pc.into();
+                cpu.b = value.into();
+                cpu.conditions = ConditionCodes::default();
+                cpu.conditions.cy = cy;
+                cpu.emulate(&mut Facade).unwrap();
+
+                assert_eq!((*cpu.b, u8::from(cpu.conditions)), (answer, flag_word(answer, ac, cy)),
+                           "{:?} with B={:02x}", Opcode::from(pc as u8 + 4), value);
+            }
+        }
+    }
+
+    #[test]
+    fn daa_corrects_every_bcd_sum() {
+        let bcd = |value: u8| ((value / 10) << 4) | (value % 10);
+        let mut cpu = Cpu::new();
+        assemble(&mut cpu, "ADC B\nDAA");
+
+        for (x, y) in (0..100).flat_map(|x| (0..100).map(move |y| (x, y))) {
+            for cy in [false, true] {
+                let sum = x + y + cy as u8;
+
+                cpu.pc = 0u16.into();
+                cpu.a = bcd(x).into();
+                cpu.b = bcd(y).into();
+                cpu.conditions.cy = cy;
+                cpu.emulate(&mut Facade).unwrap();
+                cpu.emulate(&mut Facade).unwrap();
+
+                assert_eq!((*cpu.a, cpu.conditions.cy), (bcd(sum % 100), sum >= 100),
+                           "{:02x} + {:02x} + {}", bcd(x), bcd(y), cy as u8);
+            }
+        }
+    }
+
+    #[test]
+    fn dad_carries_out_of_sixteen_bits() {
+        let cases: [(u16, u16, u16, bool); 4] = [(0xffff, 0x0001, 0x0000, true),
+                                                  (0x0100, 0x0000, 0x0100, false),
+                                                  (0x8000, 0x8000, 0x0000, true),
+                                                  (0x7fff, 0x8000, 0xffff, false)];
+        let mut cpu = Cpu::new();
+        assemble(&mut cpu, "DAD B\nDAD SP");
+
+        for (hl, second, answer, cy) in cases {
+            for pc in [0u16, 1] {
+                cpu.pc = pc.into();
+                cpu.h = (hl >> 8).into();
+                cpu.l = hl.into();
+                cpu.b = (second >> 8).into();
+                cpu.c = second.into();
+                cpu.sp = second.into();
+                cpu.conditions.cy = !cy;
+                cpu.emulate(&mut Facade).unwrap();
+
+                assert_eq!(((*cpu.h as u16) << 8 | *cpu.l as u16, cpu.conditions.cy),
+                           (answer, cy),
+                           "{:?} with HL={:04x}", Opcode::from(pc as u8 * 0x30 + 0x09), hl);
+            }
+        }
+    }
+
     #[test]
     fn adi() {
         let mut cpu = Cpu::new();
 
         cpu.a = 0u16.into();
-        cpu.load_into_rom(&[0xc6, 6], 0);
-        cpu.emulate(&mut Facade);
+        assemble(&mut cpu, "ADI 6");
+        cpu.emulate(&mut Facade).unwrap();
 
         assert_eq!(cpu.a, 6);
         assert!(!cpu.conditions.cy);
@@ -1145,9 +1501,9 @@ mod tests {
         let mut cpu = Cpu::new();
 
         cpu.a = 0x9bu16.into();
-        cpu.load_into_rom(&[0x27], 0);
+        assemble(&mut cpu, "DAA");
 
-        cpu.emulate(&mut Facade);
+        cpu.emulate(&mut Facade).unwrap();
 
         assert_eq!(cpu.a, 1);
 
@@ -1161,9 +1517,9 @@ mod tests {
         let mut cpu = Cpu::new();
 
         cpu.a = 0xb5u16.into();
-        cpu.load_into_rom(&[0x17], 0);
+        assemble(&mut cpu, "RAL");
 
-        cpu.emulate(&mut Facade);
+        cpu.emulate(&mut Facade).unwrap();
 
         assert_eq!(cpu.a, 0x6a);
         assert!(cpu.conditions.cy);
@@ -1174,11 +1530,11 @@ mod tests {
     fn rlc() {
         let mut cpu = Cpu::new();
 
-        cpu.load_into_rom(&[0x07], 0);
+        assemble(&mut cpu, "RLC");
 
         cpu.a = 0xf2u8.into();
 
-        cpu.emulate(&mut Facade);
+        cpu.emulate(&mut Facade).unwrap();
 
         assert_eq!(*cpu.a, 0xe5);
         assert!(cpu.conditions.cy);
@@ -1188,12 +1544,12 @@ mod tests {
     fn rar() {
         let mut cpu = Cpu::new();
 
-        cpu.load_into_rom(&[0x1f], 0);
+        assemble(&mut cpu, "RAR");
 
         cpu.a = 0x6au8.into();
         cpu.conditions.cy = true;
 
-        cpu.emulate(&mut Facade);
+        cpu.emulate(&mut Facade).unwrap();
 
         assert_eq!(*cpu.a, 0xb5);
         assert!(!cpu.conditions.cy);
@@ -1202,7 +1558,7 @@ mod tests {
     #[test]
     fn push_pop() {
         macro_rules! push_pop {
-            ($x:ident $y:ident, $push:expr, $pop:expr) => {{
+            ($x:ident $y:ident, $pair:expr) => {{
                 let mut cpu = Cpu::new();
 
                 let $x = 7u8.into();
@@ -1211,23 +1567,281 @@ mod tests {
                 cpu.$x = $x;
                 cpu.$y = $y;
 
-                cpu.load_into_rom(&[$push, $pop], 0);
-                cpu.emulate(&mut Facade);
+                assemble(&mut cpu, &format!("PUSH {0}\nPOP {0}", $pair));
+                cpu.emulate(&mut Facade).unwrap();
 
                 cpu.$x = 0u8.into();
                 cpu.$y = 0u8.into();
 
-                cpu.emulate(&mut Facade);
+                cpu.emulate(&mut Facade).unwrap();
 
                 assert_eq!(cpu.$x, $x);
                 assert_eq!(cpu.$y, $y);
             }}
         }
 
-        push_pop!(b c, 0xc5, 0xc1);
-        push_pop!(d e, 0xd5, 0xd1);
-        push_pop!(h l, 0xe5, 0xe1);
-        push_pop!(a conditions, 0xf5, 0xf1);
+        push_pop!(b c, "B");
+        push_pop!(d e, "D");
+        push_pop!(h l, "H");
+        push_pop!(a conditions, "PSW");
+    }
+
+    #[test]
+    fn conditional_cycles() {
+        let mut cpu = Cpu::new();
+
+        cpu.sp = 0x100u16.into();
+        assemble(&mut cpu, "
+                CNZ SUB
+                CZ 0
+        SUB:    RZ
+                RNZ
+        ");
+
+        assert_eq!(cpu.emulate(&mut Facade), Ok(17));
+        assert_eq!(*cpu.pc, 6);
+        assert_eq!(cpu.emulate(&mut Facade), Ok(5));
+        assert_eq!(cpu.emulate(&mut Facade), Ok(11));
+        assert_eq!(*cpu.pc, 3);
+        assert_eq!(cpu.emulate(&mut Facade), Ok(11));
+        assert_eq!(*cpu.pc, 6);
+
+        assert_eq!(cpu.cycles, 17 + 5 + 11 + 11);
+    }
+
+    #[test]
+    fn hlt() {
+        let mut cpu = Cpu::new();
+
+        assemble(&mut cpu, "HLT");
+
+        assert_eq!(cpu.emulate(&mut Facade), Ok(7));
+        assert!(cpu.halted);
+        assert_eq!(*cpu.pc, 1);
+
+        assert_eq!(cpu.emulate(&mut Facade), Err(CpuError::Halted));
+        assert_eq!(*cpu.pc, 1);
+    }
+
+    #[test]
+    fn hlt_wakes_on_interrupt() {
+        let mut cpu = Cpu::new();
+
+        cpu.sp = 0x100u16.into();
+        assemble(&mut cpu, "EI\nHLT");
+        cpu.emulate(&mut Facade).unwrap();
+        cpu.emulate(&mut Facade).unwrap();
+
+        assert_eq!(cpu.emulate(&mut Facade), Ok(HALT_CYCLES));
+        assert!(cpu.halted);
+        assert_eq!(*cpu.pc, 2);
+
+        cpu.request_interrupt(0xcf);
+
+        assert_eq!(cpu.emulate(&mut Facade), Ok(11));
+        assert!(!cpu.halted);
+        assert!(!cpu.int_enable);
+        assert_eq!(*cpu.pc, 8);
+        assert_eq!(cpu.memory[0xfeu16], 2);
+    }
+
+    #[test]
+    fn ei_delays_interrupts_by_one_instruction() {
+        let mut cpu = Cpu::new();
+
+        cpu.sp = 0x100u16.into();
+        assemble(&mut cpu, "EI\nNOP\nNOP");
+        cpu.request_interrupt(0xd7);
+
+        cpu.emulate(&mut Facade).unwrap();
+        assert_eq!(*cpu.pc, 1);
+        cpu.emulate(&mut Facade).unwrap();
+        assert_eq!(*cpu.pc, 2);
+        assert!(cpu.interrupt_pending());
+
+        cpu.emulate(&mut Facade).unwrap();
+        assert_eq!(*cpu.pc, 0x10);
+        assert!(!cpu.interrupt_pending());
+        assert_eq!(cpu.memory[0xfeu16], 2);
+    }
+
+    #[test]
+    fn rst_returns_past_itself() {
+        let mut cpu = Cpu::new();
+
+        cpu.sp = 0x100u16.into();
+        assemble(&mut cpu, "
+                ORG 8
+                RET
+                ORG 40H
+                NOP
+                RST 1
+        ");
+        cpu.pc = 0x40u16.into();
+
+        cpu.emulate(&mut Facade).unwrap();
+        cpu.emulate(&mut Facade).unwrap();
+        assert_eq!(*cpu.pc, 0x08);
+        cpu.emulate(&mut Facade).unwrap();
+        assert_eq!(*cpu.pc, 0x42);
+    }
+
+    #[test]
+    fn interrupt_with_call() {
+        let mut cpu = Cpu::new();
+
+        cpu.sp = 0x100u16.into();
+        cpu.int_enable = true;
+        cpu.pc = 0x40u16.into();
+        cpu.request_interrupt(Interrupt::call(0x1234));
+
+        assert_eq!(cpu.emulate(&mut Facade), Ok(17));
+        assert_eq!(*cpu.pc, 0x1234);
+        assert_eq!(*cpu.sp, 0xfe);
+        assert_eq!(cpu.memory[0xfeu16], 0x40);
+        assert_eq!(cpu.memory[0xffu16], 0x00);
+        assert!(!cpu.int_enable);
+    }
+
+    #[test]
+    fn interrupts_held_while_disabled() {
+        let mut cpu = Cpu::new();
+
+        assemble(&mut cpu, "NOP\nNOP");
+        cpu.request_interrupt(0xd7);
+
+        cpu.emulate(&mut Facade).unwrap();
+        cpu.emulate(&mut Facade).unwrap();
+
+        assert_eq!(*cpu.pc, 2);
+        assert!(cpu.interrupt_pending());
+    }
+
+    #[test]
+    fn undocumented_aliases() {
+        let mut cpu = Cpu::new();
+
+        assemble(&mut cpu, "NOP\nDB 10H, 18H, 0CBH\nDW 10H");
+        cpu.emulate(&mut Facade).unwrap();
+
+        assert_eq!(cpu.emulate(&mut Facade), Ok(4));
+        assert_eq!(cpu.emulate(&mut Facade), Ok(4));
+        assert_eq!(*cpu.pc, 3);
+        assert_eq!(cpu.emulate(&mut Facade), Ok(10));
+        assert_eq!(*cpu.pc, 0x10);
+    }
+
+    #[test]
+    fn load_image_jumps_to_start() {
+        let mut cpu = Cpu::new();
+        let mut image = Image::new(0x100, vec![0x3c]);
+        image.start = Some(0x100);
+
+        cpu.load_image(&image);
+        cpu.emulate(&mut Facade).unwrap();
+
+        assert_eq!(*cpu.pc, 0x101);
+        assert_eq!(cpu.a, 1);
+    }
+
+    #[test]
+    fn custom_bus() {
+        struct Latch {
+            rom: [u8; 8],
+            latch: u8,
+        }
+
+        impl Bus for Latch {
+            fn peek(&self, address: u16) -> u8 {
+                self.rom.get(address as usize).copied().unwrap_or(self.latch)
+            }
+
+            fn write(&mut self, address: u16, value: u8) {
+                if address as usize >= self.rom.len() {
+                    self.latch = value;
+                }
+            }
+        }
+
+        let rom = asm::assemble("MVI A,42H\nSTA 8000H\nSTA 0").unwrap().bytes;
+        let rom: [u8; 8] = rom.try_into().unwrap();
+        let mut cpu = Cpu::with_bus(Latch { rom, latch: 0 });
+
+        for _ in 0..3 {
+            cpu.emulate(&mut Facade).unwrap();
+        }
+
+        assert_eq!(cpu.memory.latch, 0x42);
+        assert_eq!(cpu.memory.rom, rom);
+    }
+
+    #[test]
+    fn observer() {
+        #[derive(Default)]
+        struct Log(Vec<String>);
+
+        impl Observer for Log {
+            fn on_fetch(&mut self, pc: u16, opcode: Opcode) {
+                self.0.push(format!("fetch {:04x} {:?}", pc, opcode));
+            }
+
+            fn on_mem_read(&mut self, address: u16, value: u8) {
+                self.0.push(format!("read {:04x} {:02x}", address, value));
+            }
+
+            fn on_mem_write(&mut self, address: u16, value: u8) {
+                self.0.push(format!("write {:04x} {:02x}", address, value));
+            }
+
+            fn on_io(&mut self, port: u8, value: u8, access: Access) {
+                self.0.push(format!("io {:02x} {:02x} {:?}", port, value, access));
+            }
+
+            fn on_interrupt(&mut self, pc: u16, interrupt: Interrupt) {
+                self.0.push(format!("interrupt {:04x} {:?}", pc, interrupt.opcode()));
+            }
+        }
+
+        let mut cpu = Cpu::with_observer(Memory::default(), Log::default());
+        cpu.sp = 0x100u16.into();
+        assemble(&mut cpu, "EI\nLDA 10H\nOUT 2\nIN 3");
+
+        for _ in 0..3 {
+            cpu.emulate(&mut Facade).unwrap();
+        }
+
+        cpu.request_interrupt(0xc7);
+        cpu.emulate(&mut Facade).unwrap();
+
+        assert_eq!(cpu.observer.0, [
+            "fetch 0000 EI",
+            "fetch 0001 LDA",
+            "read 0010 00",
+            "fetch 0004 OUT",
+            "io 02 00 Write",
+            "interrupt 0006 RST 0",
+            "write 00ff 00",
+            "write 00fe 06",
+        ]);
     }
 
+    #[test]
+    fn traps() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0x100u16.into();
+        cpu.traps.insert(5);
+        assemble(&mut cpu, "CALL 5\nHLT\nORG 5\nINR A\nRET");
+
+        cpu.emulate(&mut Facade).unwrap();
+        assert_eq!(cpu.emulate(&mut Facade), Err(CpuError::Trap { pc: 5 }));
+        assert_eq!(*cpu.a, 0);
+
+        cpu.emulate(&mut Facade).unwrap();
+        cpu.emulate(&mut Facade).unwrap();
+        assert_eq!(*cpu.a, 1);
+        assert_eq!(*cpu.pc, 3);
+
+        cpu.pc = 5u16.into();
+        assert_eq!(cpu.emulate(&mut Facade), Err(CpuError::Trap { pc: 5 }));
+    }
 }
@@ -0,0 +1,260 @@
+use std::collections::BTreeSet;
+use std::mem;
+
+use crate::bus::Bus;
+use crate::cpu::{Cpu, CpuError, Machine};
+use crate::interrupt::Interrupt;
+use crate::observer::{Access, Observer};
+use crate::opcode::Opcode;
+use crate::rewind::Rewind;
+
+/// Why `Cpu::run_until` returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The condition passed to `run_until` was met.
+    Condition,
+    /// The next instruction is at a breakpoint.
+    Breakpoint { pc: u16 },
+    /// The last instruction accessed a watched address.
+    Watchpoint { address: u16, value: u8, access: Access },
+    Error(CpuError),
+}
+
+/// An `Observer` holding the breakpoints and watchpoints checked by
+/// `Cpu::run_until`.
+#[derive(Clone, Debug, Default)]
+pub struct Debugger {
+    pub breakpoints: BTreeSet<u16>,
+    /// Watched addresses, each paired with the kind of access that stops
+    /// execution.
+    pub watchpoints: BTreeSet<(u16, Access)>,
+    /// Snapshots taken by `run_until` for `step_back`, when set.
+    pub rewind: Option<Rewind>,
+    fetched: Option<(u16, Opcode)>,
+    hit: Option<StopReason>,
+    executed: u64,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The address and opcode of the last instruction fetched from memory.
+    pub fn last_fetch(&self) -> Option<(u16, Opcode)> {
+        self.fetched
+    }
+
+    /// The number of instructions started, including those supplied by
+    /// interrupts.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    fn access(&mut self, address: u16, value: u8, access: Access) {
+        if self.hit.is_none() && self.watchpoints.contains(&(address, access)) {
+            self.hit = Some(StopReason::Watchpoint { address, value, access });
+        }
+    }
+}
+
+impl Observer for Debugger {
+    fn on_fetch(&mut self, pc: u16, opcode: Opcode) {
+        self.fetched = Some((pc, opcode));
+        self.executed += 1;
+    }
+
+    fn on_interrupt(&mut self, _pc: u16, _interrupt: Interrupt) {
+        self.executed += 1;
+    }
+
+    fn on_mem_read(&mut self, address: u16, value: u8) {
+        self.access(address, value, Access::Read);
+    }
+
+    fn on_mem_write(&mut self, address: u16, value: u8) {
+        self.access(address, value, Access::Write);
+    }
+}
+
+impl<B: Bus> Cpu<B, Debugger> {
+    /// Runs until `until` returns true after an instruction, execution
+    /// reaches a breakpoint, an instruction touches a watchpoint or the CPU
+    /// stops with an error. A breakpoint at the current PC is ignored, so a
+    /// stopped program can simply be resumed.
+    pub fn run_until<M, F>(&mut self, machine: &mut M, mut until: F) -> StopReason
+        where M: Machine,
+              F: FnMut(&Self) -> bool,
+    {
+        let mut first = true;
+        self.observer.hit = None;
+
+        loop {
+            if !mem::take(&mut first) && self.observer.breakpoints.contains(&*self.pc) {
+                return StopReason::Breakpoint { pc: *self.pc };
+            }
+
+            self.snapshot();
+
+            if let Err(error) = self.emulate(machine) {
+                return StopReason::Error(error);
+            }
+
+            if let Some(hit) = self.observer.hit.take() {
+                return hit;
+            }
+
+            if until(self) {
+                return StopReason::Condition;
+            }
+        }
+    }
+
+    /// Returns to the state from before the last `count` instructions by
+    /// restoring the closest earlier snapshot and running forward from it
+    /// with breakpoints and watchpoints disabled. The result is exact as long
+    /// as `machine` answers the same way it did the first time. Returns
+    /// `false`, changing nothing, if the snapshots don't reach back that far
+    /// or the run forward stops short, such as at a trap or an error.
+    pub fn step_back<M: Machine>(&mut self, machine: &mut M, count: u64) -> bool {
+        let target = match self.observer.executed.checked_sub(count) {
+            Some(target) => target,
+            None => return false,
+        };
+        let mut rewind = match self.observer.rewind.take() {
+            Some(rewind) => rewind,
+            None => return false,
+        };
+
+        let current = self.save_state(&());
+        let executed = self.observer.executed;
+        let position = match rewind.seek(target) {
+            Some((position, state)) if self.load_state(&mut (), state).is_ok() => position,
+            _ => {
+                self.observer.rewind = Some(rewind);
+                return false;
+            }
+        };
+
+        let breakpoints = mem::take(&mut self.observer.breakpoints);
+        let watchpoints = mem::take(&mut self.observer.watchpoints);
+        self.observer.rewind = Some(rewind);
+        self.observer.executed = position;
+
+        while self.observer.executed < target && !self.halted && self.emulate(machine).is_ok() {}
+
+        let reached = self.observer.executed == target;
+
+        if !reached {
+            self.load_state(&mut (), &current).unwrap();
+            self.observer.executed = executed;
+        }
+
+        self.observer.breakpoints = breakpoints;
+        self.observer.watchpoints = watchpoints;
+        self.observer.hit = None;
+        reached
+    }
+
+    fn snapshot(&mut self) {
+        let executed = self.observer.executed;
+
+        if self.observer.rewind.as_ref().is_some_and(|rewind| rewind.due(executed)) {
+            let state = self.save_state(&());
+            self.observer.rewind.as_mut().unwrap().push(executed, state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::Entry;
+    use crate::{asm, Memory};
+
+    struct Null;
+
+    impl Machine for Null {
+        fn input(&mut self, _: u8) -> u8 { 0 }
+        fn output(&mut self, _: u8, _: u8) {}
+    }
+
+    fn cpu(source: &str) -> Cpu<Memory, Debugger> {
+        let assembly = asm::assemble(source).unwrap();
+        let mut cpu = Cpu::with_observer(Memory::default(), Debugger::new());
+        cpu.load_into_rom(&assembly.bytes, assembly.origin);
+        cpu
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut cpu = cpu("LOOP: INR A\nJMP LOOP\nHLT");
+        cpu.observer.breakpoints.insert(0);
+
+        assert_eq!(cpu.run_until(&mut Null, |_| false), StopReason::Breakpoint { pc: 0 });
+        assert_eq!(cpu.run_until(&mut Null, |_| false), StopReason::Breakpoint { pc: 0 });
+        assert_eq!(*cpu.a, 2);
+        assert_eq!(cpu.observer.last_fetch().map(|(pc, _)| pc), Some(1));
+
+        cpu.observer.breakpoints.clear();
+        assert_eq!(cpu.run_until(&mut Null, |cpu| *cpu.a == 5), StopReason::Condition);
+        assert_eq!(*cpu.pc, 1);
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut cpu = cpu("LXI H,100H\nMOV A,M\nMVI M,42H\nLDA 100H\nHLT");
+        cpu.observer.watchpoints.insert((0x100, Access::Write));
+
+        assert_eq!(cpu.run_until(&mut Null, |_| false),
+                   StopReason::Watchpoint { address: 0x100, value: 0x42, access: Access::Write });
+        assert_eq!(*cpu.pc, 6);
+
+        cpu.observer.watchpoints.insert((0x100, Access::Read));
+        assert_eq!(cpu.run_until(&mut Null, |_| false),
+                   StopReason::Watchpoint { address: 0x100, value: 0x42, access: Access::Read });
+        assert_eq!(cpu.run_until(&mut Null, |_| false), StopReason::Error(CpuError::Halted));
+    }
+
+    #[test]
+    fn step_back_matches_forward_trace() {
+        let mut cpu = cpu("LXI SP,100H\nLXI H,200H\nLOOP: INR M\nCALL SUB\nJMP LOOP\n\
+                           SUB: ADD M\nPUSH PSW\nPOP B\nRET");
+        let mut forward = Vec::new();
+        cpu.observer.rewind = Some(Rewind::new(16, 5));
+
+        for _ in 0..40 {
+            forward.push((Entry::capture(&cpu), cpu.memory.memory.clone()));
+            assert_eq!(cpu.run_until(&mut Null, |_| true), StopReason::Condition);
+        }
+
+        cpu.observer.breakpoints.insert(*cpu.pc);
+
+        while let Some((entry, memory)) = forward.pop() {
+            assert!(cpu.step_back(&mut Null, 1));
+            assert_eq!(Entry::capture(&cpu), entry);
+            assert!(cpu.memory.memory == memory);
+        }
+
+        assert_eq!(cpu.observer.executed(), 0);
+        assert!(!cpu.step_back(&mut Null, 1));
+        assert_eq!(cpu.observer.breakpoints.len(), 1);
+    }
+
+    #[test]
+    fn step_back_fails_if_the_replay_stops_short() {
+        let mut cpu = cpu("INR A\nINR A\nINR A\nINR A\nHLT");
+        cpu.observer.rewind = Some(Rewind::new(16, 5));
+
+        for _ in 0..4 {
+            cpu.run_until(&mut Null, |_| true);
+        }
+
+        let before = Entry::capture(&cpu);
+        cpu.traps.insert(1);
+
+        assert!(!cpu.step_back(&mut Null, 1));
+        assert_eq!(Entry::capture(&cpu), before);
+        assert_eq!(cpu.observer.executed(), 4);
+    }
+}
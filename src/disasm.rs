@@ -0,0 +1,416 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::opcode::{Opcode, OpcodeInfo, Operand, Pair, Reg};
+
+/// A decoded operand value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Argument {
+    Reg(Reg),
+    Pair(Pair),
+    Byte(u8),
+    Word(u16),
+    Address(u16),
+    Port(u8),
+    Vector(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,
+    bytes: [u8; 3],
+    len: u8,
+}
+
+/// A linear sweep over `bytes`, which are loaded at `origin`.
+pub struct Disassembly<'a> {
+    bytes: &'a [u8],
+    address: u16,
+}
+
+pub fn disassemble(bytes: &[u8], origin: u16) -> Disassembly<'_> {
+    Disassembly { bytes, address: origin }
+}
+
+impl<'a> Iterator for Disassembly<'a> {
+    type Item = Instruction;
+
+    fn next(&mut self) -> Option<Instruction> {
+        let instruction = Instruction::decode(self.bytes, self.address)?;
+        let len = instruction.bytes().len();
+
+        self.bytes = &self.bytes[len..];
+        self.address = self.address.wrapping_add(len as u16);
+        Some(instruction)
+    }
+}
+
+impl Instruction {
+    /// Decodes the instruction at the start of `bytes`. If `bytes` ends
+    /// before the instruction does the result is incomplete, and is
+    /// displayed as data.
+    pub fn decode(bytes: &[u8], address: u16) -> Option<Instruction> {
+        let opcode = Opcode::from(*bytes.first()?);
+        let len = bytes.len().min(opcode.size() as usize);
+        let mut buffer = [0; 3];
+        buffer[..len].copy_from_slice(&bytes[..len]);
+
+        Some(Instruction { address, bytes: buffer, len: len as u8 })
+    }
+
+    pub fn opcode(&self) -> Opcode {
+        Opcode::from(self.bytes[0])
+    }
+
+    pub fn info(&self) -> &'static OpcodeInfo {
+        self.opcode().info()
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        self.info().mnemonic
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.len == self.opcode().size()
+    }
+
+    /// The address of the instruction that follows this one.
+    pub fn next_address(&self) -> u16 {
+        self.address.wrapping_add(self.len as u16)
+    }
+
+    pub fn arguments(&self) -> impl Iterator<Item = Argument> + '_ {
+        let operands = if self.is_complete() { self.info().operands } else { &[] };
+        let byte = self.bytes[1];
+        let word = (self.bytes[2] as u16) << 8 | self.bytes[1] as u16;
+
+        operands.iter().map(move |operand| match *operand {
+            Operand::Reg(reg) => Argument::Reg(reg),
+            Operand::Pair(pair) => Argument::Pair(pair),
+            Operand::D8 => Argument::Byte(byte),
+            Operand::D16 => Argument::Word(word),
+            Operand::Addr => Argument::Address(word),
+            Operand::Port => Argument::Port(byte),
+            Operand::Vector(n) => Argument::Vector(n),
+        })
+    }
+
+    /// The destination of a jump, call or RST.
+    pub fn branch_target(&self) -> Option<u16> {
+        match *self.opcode() {
+            0xc7 | 0xcf | 0xd7 | 0xdf | 0xe7 | 0xef | 0xf7 | 0xff => {
+                Some((*self.opcode() & 0x38) as u16)
+            }
+            0xc2 | 0xc3 | 0xc4 | 0xca | 0xcc | 0xcd |
+            0xd2 | 0xd4 | 0xda | 0xdc |
+            0xe2 | 0xe4 | 0xea | 0xec |
+            0xf2 | 0xf4 | 0xfa | 0xfc => {
+                match self.arguments().next() {
+                    Some(Argument::Address(target)) => Some(target),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether execution never continues to the following instruction.
+    pub fn ends_flow(&self) -> bool {
+        matches!(*self.opcode(), 0xc3 | 0xc9 | 0xe9)
+    }
+
+    /// Written as `DB` so that assembling the output reproduces the bytes.
+    fn is_data(&self) -> bool {
+        !self.is_complete() || self.info().undocumented
+    }
+}
+
+/// A line of a traced listing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Line {
+    Code(Instruction),
+    Data { address: u16, bytes: Vec<u8> },
+}
+
+/// The result of a recursive-descent disassembly. Displays as source that
+/// assembles back to the original image.
+#[derive(Clone, Debug)]
+pub struct Listing {
+    pub origin: u16,
+    pub lines: Vec<Line>,
+    pub labels: BTreeSet<u16>,
+}
+
+const DATA_PER_LINE: usize = 8;
+
+/// Disassembles `image`, loaded at `origin`, by following the flow of control
+/// from each of `entries`. Jump, call and RST targets are traced and
+/// labelled; bytes that are never reached are emitted as `DB` data.
+pub fn trace(image: &[u8], origin: u16, entries: &[u16]) -> Listing {
+    let offset_of = |address: u16| {
+        let offset = address.wrapping_sub(origin) as usize;
+        if offset < image.len() { Some(offset) } else { None }
+    };
+
+    let mut claimed = vec![false; image.len()];
+    let mut starts = BTreeSet::new();
+    let mut labels = BTreeSet::new();
+    let mut pending: Vec<u16> = entries.to_vec();
+
+    labels.extend(entries.iter().filter(|&&entry| offset_of(entry).is_some()));
+
+    while let Some(mut address) = pending.pop() {
+        while let Some(offset) = offset_of(address) {
+            if starts.contains(&offset) {
+                break;
+            }
+
+            let instruction = match Instruction::decode(&image[offset..], address) {
+                Some(instruction) if !instruction.is_data() => instruction,
+                _ => break,
+            };
+
+            let end = offset + instruction.bytes().len();
+            if claimed[offset..end].iter().any(|&claimed| claimed) {
+                break;
+            }
+
+            claimed[offset..end].iter_mut().for_each(|claimed| *claimed = true);
+            starts.insert(offset);
+
+            if let Some(target) = instruction.branch_target() {
+                if offset_of(target).is_some() {
+                    labels.insert(target);
+                    pending.push(target);
+                }
+            }
+
+            if instruction.ends_flow() {
+                break;
+            }
+
+            address = instruction.next_address();
+        }
+    }
+
+    // A target in the middle of another instruction can't be labelled.
+    labels.retain(|&address| offset_of(address).is_some_and(|offset| starts.contains(&offset)));
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+
+    while offset < image.len() {
+        let address = origin.wrapping_add(offset as u16);
+
+        if starts.contains(&offset) {
+            let instruction = Instruction::decode(&image[offset..], address).unwrap();
+            offset += instruction.bytes().len();
+            lines.push(Line::Code(instruction));
+        } else {
+            let end = (offset..image.len())
+                .take(DATA_PER_LINE)
+                .find(|&end| claimed[end])
+                .unwrap_or_else(|| image.len().min(offset + DATA_PER_LINE));
+
+            lines.push(Line::Data { address, bytes: image[offset..end].to_vec() });
+            offset = end;
+        }
+    }
+
+    Listing { origin, lines, labels }
+}
+
+impl Line {
+    pub fn address(&self) -> u16 {
+        match *self {
+            Line::Code(ref instruction) => instruction.address,
+            Line::Data { address, .. } => address,
+        }
+    }
+}
+
+fn label(address: u16) -> String {
+    format!("L{:04X}", address)
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "\t{:<8}{}", "ORG", Hex(self.origin, 4))?;
+
+        for line in &self.lines {
+            let address = line.address();
+            if self.labels.contains(&address) {
+                writeln!(f, "{}:", label(address))?;
+            }
+
+            let text = match *line {
+                Line::Code(ref instruction) => {
+                    let arguments = instruction.arguments()
+                        .map(|argument| match argument {
+                            Argument::Address(target) if self.labels.contains(&target) => {
+                                label(target)
+                            }
+                            argument => argument.to_string(),
+                        })
+                        .collect::<Vec<_>>();
+
+                    format!("{:<8}{}", instruction.mnemonic(), arguments.join(","))
+                }
+                Line::Data { ref bytes, .. } => {
+                    let bytes = bytes.iter()
+                        .map(|&byte| Hex(byte as u16, 2).to_string())
+                        .collect::<Vec<_>>();
+
+                    format!("{:<8}{}", "DB", bytes.join(","))
+                }
+            };
+
+            writeln!(f, "\t{:<24}; {:04X}", text.trim_end(), address)?;
+        }
+
+        writeln!(f, "\tEND")
+    }
+}
+
+/// Formats numbers in Intel syntax, e.g. `0FFH`.
+pub(crate) struct Hex(pub u16, pub usize);
+
+impl fmt::Display for Hex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = format!("{:01$X}", self.0, self.1);
+
+        if digits.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            write!(f, "0{}H", digits)
+        } else {
+            write!(f, "{}H", digits)
+        }
+    }
+}
+
+impl fmt::Display for Argument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Argument::Reg(reg) => write!(f, "{}", reg),
+            Argument::Pair(pair) => write!(f, "{}", pair),
+            Argument::Byte(byte) | Argument::Port(byte) => write!(f, "{}", Hex(byte as u16, 2)),
+            Argument::Word(word) | Argument::Address(word) => write!(f, "{}", Hex(word, 4)),
+            Argument::Vector(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_data() {
+            write!(f, "DB")?;
+
+            let mut separator = " ";
+            for byte in self.bytes() {
+                write!(f, "{}{}", separator, Hex(*byte as u16, 2))?;
+                separator = ",";
+            }
+
+            return Ok(());
+        }
+
+        write!(f, "{}", self.mnemonic())?;
+
+        let mut separator = " ";
+        for argument in self.arguments() {
+            write!(f, "{}{}", separator, argument)?;
+            separator = ",";
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(bytes: &[u8], origin: u16) -> Vec<String> {
+        disassemble(bytes, origin).map(|instruction| instruction.to_string()).collect()
+    }
+
+    #[test]
+    fn intel_syntax() {
+        let bytes = [
+            0x00,
+            0x31, 0x00, 0x24,
+            0x3e, 0xfe,
+            0x7e,
+            0xc3, 0xd4, 0x18,
+            0xdb, 0x01,
+            0xcf,
+            0xf5,
+        ];
+
+        assert_eq!(listing(&bytes, 0), [
+            "NOP",
+            "LXI SP,2400H",
+            "MVI A,0FEH",
+            "MOV A,M",
+            "JMP 18D4H",
+            "IN 01H",
+            "RST 1",
+            "PUSH PSW",
+        ]);
+    }
+
+    #[test]
+    fn addresses_and_bytes() {
+        let instructions: Vec<_> = disassemble(&[0x00, 0x21, 0x34, 0x12, 0x76], 0x100).collect();
+
+        assert_eq!(instructions.len(), 3);
+        assert_eq!(instructions[1].address, 0x101);
+        assert_eq!(instructions[1].bytes(), &[0x21, 0x34, 0x12]);
+        assert_eq!(instructions[1].arguments().collect::<Vec<_>>(),
+                   [Argument::Pair(Pair::H), Argument::Word(0x1234)]);
+        assert_eq!(instructions[2].address, 0x104);
+    }
+
+    #[test]
+    fn trace_separates_code_and_data() {
+        let image = [
+            0xc3, 0x06, 0x00,       // 0000 JMP 0006
+            0x41, 0x42, 0x43,       // 0003 'ABC'
+            0xcd, 0x0b, 0x00,       // 0006 CALL 000B
+            0x18, 0xfe,             // 0009 JR $ (Z80)
+            0xca, 0x0f, 0x00,       // 000B JZ 000F
+            0xc9,                   // 000E RET
+            0xcf,                   // 000F RST 1
+            0x76,                   // 0010 HLT
+        ];
+
+        let listing = trace(&image, 0, &[0]);
+        let addresses = listing.lines.iter().map(Line::address).collect::<Vec<_>>();
+
+        assert_eq!(addresses, [0x00, 0x03, 0x06, 0x09, 0x0b, 0x0e, 0x0f, 0x10]);
+        assert_eq!(listing.lines[1], Line::Data { address: 3, bytes: vec![0x41, 0x42, 0x43] });
+        assert_eq!(listing.lines[3], Line::Data { address: 9, bytes: vec![0x18, 0xfe] });
+        assert_eq!(listing.labels.iter().copied().collect::<Vec<_>>(), [0x00, 0x06, 0x0b, 0x0f]);
+
+        let source = listing.to_string();
+        assert!(source.contains("L0000:\n\tJMP     L0006"));
+        assert!(source.contains("\tDB      41H,42H,43H"));
+        assert!(source.contains("\tRST     1"));
+
+        let assembly = crate::asm::assemble(&source).unwrap();
+        assert_eq!(assembly.origin, 0);
+        assert_eq!(assembly.bytes, image);
+    }
+
+    #[test]
+    fn undocumented_and_truncated_are_data() {
+        assert_eq!(listing(&[0x08, 0xcb, 0x00, 0x10, 0xc3, 0x00], 0), [
+            "DB 08H",
+            "DB 0CBH,00H,10H",
+            "DB 0C3H,00H",
+        ]);
+    }
+}
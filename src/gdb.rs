@@ -0,0 +1,487 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::ops::Range;
+
+use crate::bus::Bus;
+use crate::cpu::{Cpu, CpuError, Machine};
+use crate::debugger::{Debugger, StopReason};
+use crate::image::hex_bytes;
+use crate::observer::Access;
+
+/// Instructions run between checks for an interrupt from GDB while
+/// continuing.
+const POLL_INTERVAL: u32 = 0x1000;
+
+/// The size in bytes of each register in `g` packets, in register number
+/// order: a, bc, de, hl, sp, pc and the flag word.
+const REGISTER_SIZES: [usize; 7] = [1, 2, 2, 2, 2, 2, 1];
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.i8080.core">
+    <flags id="i8080_flags" size="1">
+      <field name="cy" start="0" end="0"/>
+      <field name="p" start="2" end="2"/>
+      <field name="ac" start="4" end="4"/>
+      <field name="z" start="6" end="6"/>
+      <field name="s" start="7" end="7"/>
+    </flags>
+    <reg name="a" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="bc" bitsize="16" type="uint16"/>
+    <reg name="de" bitsize="16" type="uint16"/>
+    <reg name="hl" bitsize="16" type="uint16"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="flags" bitsize="8" type="i8080_flags"/>
+  </feature>
+</target>
+"#;
+
+/// A GDB remote serial protocol stub serving a single connection. It supports
+/// register and memory access, software breakpoints, watchpoints, single
+/// stepping and continuing, which GDB can interrupt with Ctrl-C.
+pub struct Stub {
+    stream: TcpStream,
+    ack: bool,
+}
+
+impl Stub {
+    pub fn new(stream: TcpStream) -> Self {
+        // Packets are small and strictly request/reply, so Nagle's algorithm
+        // only adds latency. Failing to disable it is harmless.
+        let _ = stream.set_nodelay(true);
+        Stub { stream, ack: true }
+    }
+
+    /// Serves requests until GDB detaches, kills the target or disconnects.
+    pub fn serve<B: Bus, M: Machine>(&mut self,
+                                     cpu: &mut Cpu<B, Debugger>,
+                                     machine: &mut M)
+        -> io::Result<()>
+    {
+        while let Some(packet) = self.receive()? {
+            let (command, arguments) = split_command(&packet);
+
+            let reply = match command {
+                "D" => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+                "k" => return Ok(()),
+                "s" | "c" => match resume_address(arguments) {
+                    Some(address) => {
+                        if let Some(address) = address {
+                            cpu.pc = address.into();
+                        }
+
+                        if command == "s" {
+                            stop_reply(cpu.run_until(machine, |_| true))
+                        } else {
+                            match self.resume(cpu, machine)? {
+                                Some(stop) => stop_reply(stop),
+                                None => String::from("S02"),
+                            }
+                        }
+                    }
+                    None => String::from("E01"),
+                },
+                _ => request(&packet, cpu).unwrap_or_else(|| String::from("E01")),
+            };
+
+            self.send(&reply)?;
+
+            if packet == "QStartNoAckMode" {
+                self.ack = false;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs until the debugger stops the CPU, or returns `None` if GDB sends
+    /// an interrupt first.
+    fn resume<B: Bus, M: Machine>(&mut self, cpu: &mut Cpu<B, Debugger>, machine: &mut M)
+        -> io::Result<Option<StopReason>>
+    {
+        loop {
+            let mut count = 0;
+            let stop = cpu.run_until(machine, |_| {
+                count += 1;
+                count == POLL_INTERVAL
+            });
+
+            if stop != StopReason::Condition {
+                return Ok(Some(stop));
+            }
+
+            if self.interrupted()? {
+                return Ok(None);
+            }
+
+            // `run_until` ignores a breakpoint on the first instruction.
+            if cpu.observer.breakpoints.contains(&*cpu.pc) {
+                return Ok(Some(StopReason::Breakpoint { pc: *cpu.pc }));
+            }
+        }
+    }
+
+    fn interrupted(&mut self) -> io::Result<bool> {
+        let mut byte = [0];
+
+        self.stream.set_nonblocking(true)?;
+        let result = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false)?;
+
+        match result {
+            Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) => Ok(byte[0] == 0x03),
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    /// Reads and acknowledges the next packet, skipping acknowledgements and
+    /// interrupts received while stopped. Returns `None` once GDB disconnects.
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.byte()? {
+                Some(b'$') => {}
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+
+            let mut data = Vec::new();
+
+            loop {
+                match self.byte()? {
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None),
+                }
+            }
+
+            let checksum = match (self.byte()?, self.byte()?) {
+                (Some(high), Some(low)) => hex_bytes(&String::from_utf8_lossy(&[high, low]), 0),
+                _ => return Ok(None),
+            };
+            let sum = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            let valid = checksum == Ok(vec![sum]);
+
+            if self.ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${}#{:02x}", data, checksum)?;
+        self.stream.flush()
+    }
+}
+
+/// Splits the single character command from the start of a packet.
+fn split_command(packet: &str) -> (&str, &str) {
+    let at = packet.char_indices().nth(1).map_or(packet.len(), |(at, _)| at);
+    packet.split_at(at)
+}
+
+/// Parses the optional resume address of `s` and `c`.
+fn resume_address(arguments: &str) -> Option<Option<u16>> {
+    match arguments {
+        "" => Some(None),
+        address => number(address).map(Some),
+    }
+}
+
+fn stop_reply(stop: StopReason) -> String {
+    match stop {
+        StopReason::Condition | StopReason::Breakpoint { .. } => String::from("S05"),
+        StopReason::Watchpoint { address, access, .. } => {
+            let kind = match access {
+                Access::Read => "rwatch",
+                Access::Write => "watch",
+            };
+
+            format!("T05{}:{:04x};", kind, address)
+        }
+        StopReason::Error(CpuError::Halted) => String::from("W00"),
+        StopReason::Error(_) => String::from("S04"),
+    }
+}
+
+/// Answers every packet that doesn't run the CPU, or returns `None` if it is
+/// malformed. Unsupported packets get an empty reply.
+fn request<B: Bus>(packet: &str, cpu: &mut Cpu<B, Debugger>) -> Option<String> {
+    let (command, arguments) = split_command(packet);
+
+    let reply = match command {
+        "?" => String::from("S05"),
+        "H" => String::from("OK"),
+        "g" => hex(&registers(cpu)),
+        "G" => {
+            let bytes = hex_bytes(arguments, 0).ok()?;
+            set_registers(cpu, bytes.as_slice().try_into().ok()?);
+            String::from("OK")
+        }
+        "p" => {
+            let range = register_range(arguments)?;
+            hex(&registers(cpu)[range])
+        }
+        "P" => {
+            let (register, value) = arguments.split_once('=')?;
+            let range = register_range(register)?;
+            let value = hex_bytes(value, 0).ok()?;
+            let mut bytes = registers(cpu);
+
+            bytes.get_mut(range).filter(|bytes| bytes.len() == value.len())?
+                .copy_from_slice(&value);
+            set_registers(cpu, &bytes);
+            String::from("OK")
+        }
+        "m" => {
+            let (address, len) = arguments.split_once(',')?;
+            let (address, len) = (number(address)?, number(len)?);
+            let bytes = (0..len)
+                .map(|i| cpu.memory.peek(address.wrapping_add(i)))
+                .collect::<Vec<_>>();
+
+            hex(&bytes)
+        }
+        "M" => {
+            let (address, data) = arguments.split_once(':')?;
+            let (address, len) = address.split_once(',')?;
+            let data = hex_bytes(data, 0).ok().filter(|data| Some(data.len()) == size(len))?;
+
+            cpu.memory.load(&data, number(address)?);
+            String::from("OK")
+        }
+        "Z" | "z" => {
+            let (kind, arguments) = arguments.split_once(',')?;
+            let (address, _) = arguments.split_once(',')?;
+            let address = number(address)?;
+            let insert = command == "Z";
+            let debugger = &mut cpu.observer;
+
+            let accesses: &[Access] = match kind {
+                "0" | "1" => {
+                    if insert {
+                        debugger.breakpoints.insert(address);
+                    } else {
+                        debugger.breakpoints.remove(&address);
+                    }
+
+                    &[]
+                }
+                "2" => &[Access::Write],
+                "3" => &[Access::Read],
+                "4" => &[Access::Read, Access::Write],
+                _ => return Some(String::new()),
+            };
+
+            for &access in accesses {
+                if insert {
+                    debugger.watchpoints.insert((address, access));
+                } else {
+                    debugger.watchpoints.remove(&(address, access));
+                }
+            }
+
+            String::from("OK")
+        }
+        _ => query(packet).unwrap_or_default(),
+    };
+
+    Some(reply)
+}
+
+fn query(packet: &str) -> Option<String> {
+    if packet.starts_with("qSupported") {
+        return Some(String::from("PacketSize=1000;qXfer:features:read+;QStartNoAckMode+"));
+    }
+
+    if let Some(arguments) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+        // The description contains none of the characters that would need
+        // escaping in a binary reply.
+        let (offset, len) = arguments.split_once(',')?;
+        let offset = usize::from_str_radix(offset, 16).ok()?.min(TARGET_XML.len());
+        let end = offset.saturating_add(usize::from_str_radix(len, 16).ok()?);
+        let more = if end < TARGET_XML.len() { "m" } else { "l" };
+
+        return Some(format!("{}{}", more, &TARGET_XML[offset..end.min(TARGET_XML.len())]));
+    }
+
+    match packet {
+        "QStartNoAckMode" => Some(String::from("OK")),
+        "qAttached" => Some(String::from("1")),
+        _ => None,
+    }
+}
+
+fn number(text: &str) -> Option<u16> {
+    u16::from_str_radix(text, 16).ok()
+}
+
+fn size(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The bytes of register `number` within the `g` packet.
+fn register_range(number: &str) -> Option<Range<usize>> {
+    let number = size(number)?;
+    let start = REGISTER_SIZES.get(..number)?.iter().sum::<usize>();
+
+    Some(start..start + REGISTER_SIZES.get(number)?)
+}
+
+/// The registers in `g` packet order. Register pairs are little endian.
+fn registers<B, O>(cpu: &Cpu<B, O>) -> [u8; 12] {
+    let [sp_low, sp_high] = cpu.sp.to_le_bytes();
+    let [pc_low, pc_high] = cpu.pc.to_le_bytes();
+
+    [
+        *cpu.a,
+        *cpu.c, *cpu.b,
+        *cpu.e, *cpu.d,
+        *cpu.l, *cpu.h,
+        sp_low, sp_high,
+        pc_low, pc_high,
+        cpu.conditions.into(),
+    ]
+}
+
+fn set_registers<B, O>(cpu: &mut Cpu<B, O>, bytes: &[u8; 12]) {
+    cpu.a = bytes[0].into();
+    cpu.c = bytes[1].into();
+    cpu.b = bytes[2].into();
+    cpu.e = bytes[3].into();
+    cpu.d = bytes[4].into();
+    cpu.l = bytes[5].into();
+    cpu.h = bytes[6].into();
+    cpu.sp = u16::from_le_bytes([bytes[7], bytes[8]]).into();
+    cpu.pc = u16::from_le_bytes([bytes[9], bytes[10]]).into();
+    cpu.conditions = bytes[11].into();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use crate::{asm, Memory};
+
+    struct Null;
+
+    impl Machine for Null {
+        fn input(&mut self, _: u8) -> u8 { 0 }
+        fn output(&mut self, _: u8, _: u8) {}
+    }
+
+    /// Starts a stub for `source` and connects to it.
+    fn connect(source: &str) -> (TcpStream, thread::JoinHandle<Cpu<Memory, Debugger>>) {
+        let assembly = asm::assemble(source).unwrap();
+        let mut cpu = Cpu::with_observer(Memory::default(), Debugger::new());
+        cpu.load_into_rom(&assembly.bytes, assembly.origin);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            Stub::new(stream).serve(&mut cpu, &mut Null).unwrap();
+            cpu
+        });
+
+        let gdb = TcpStream::connect(address).unwrap();
+        gdb.set_nodelay(true).unwrap();
+        (gdb, server)
+    }
+
+    fn request(stream: &mut TcpStream, packet: &str) -> String {
+        let checksum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(stream, "${}#{:02x}", packet, checksum).unwrap();
+
+        let mut reply = Vec::new();
+        let mut byte = [0];
+
+        while byte != *b"#" {
+            stream.read_exact(&mut byte).unwrap();
+            reply.push(byte[0]);
+        }
+
+        let mut checksum = [0; 2];
+        stream.read_exact(&mut checksum).unwrap();
+        stream.write_all(b"+").unwrap();
+
+        let reply = String::from_utf8(reply).unwrap();
+        assert!(reply.starts_with("+$"), "{}", reply);
+        reply[2..reply.len() - 1].to_owned()
+    }
+
+    #[test]
+    fn session() {
+        let (mut gdb, server) = connect("MVI A,42H\nLXI H,1234H\nSTA 2000H\nHLT");
+
+        assert!(request(&mut gdb, "qSupported:xmlRegisters=i386").contains("qXfer:features"));
+        assert!(request(&mut gdb, "qXfer:features:read:target.xml:0,20").starts_with("m<?xml"));
+        assert_eq!(request(&mut gdb, "?"), "S05");
+        assert_eq!(request(&mut gdb, "g"), "000000000000000000000002");
+
+        assert_eq!(request(&mut gdb, "s"), "S05");
+        assert_eq!(request(&mut gdb, "p0"), "42");
+
+        assert_eq!(request(&mut gdb, "Z0,5,1"), "OK");
+        assert_eq!(request(&mut gdb, "c"), "S05");
+        assert_eq!(request(&mut gdb, "p5"), "0500");
+        assert_eq!(request(&mut gdb, "p3"), "3412");
+
+        assert_eq!(request(&mut gdb, "Z2,2000,1"), "OK");
+        assert_eq!(request(&mut gdb, "c"), "T05watch:2000;");
+        assert_eq!(request(&mut gdb, "m2000,1"), "42");
+        assert_eq!(request(&mut gdb, "M2000,2:aabb"), "OK");
+        assert_eq!(request(&mut gdb, "m2000,2"), "aabb");
+
+        assert_eq!(request(&mut gdb, "P1=3412"), "OK");
+        assert_eq!(request(&mut gdb, "P6=d7"), "OK");
+        assert_eq!(request(&mut gdb, "p9"), "E01");
+        assert_eq!(request(&mut gdb, "c"), "W00");
+        assert_eq!(request(&mut gdb, "D"), "OK");
+
+        let cpu = server.join().unwrap();
+        assert_eq!((*cpu.b, *cpu.c), (0x12, 0x34));
+        assert_eq!(u8::from(cpu.conditions), 0xd7);
+    }
+
+    #[test]
+    fn interrupt() {
+        let (mut gdb, server) = connect("LOOP: JMP LOOP");
+
+        write!(gdb, "$c#63").unwrap();
+        gdb.write_all(&[0x03]).unwrap();
+
+        let mut reply = [0; 8];
+        gdb.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"+$S02#b5");
+
+        gdb.write_all(b"+$k#6b").unwrap();
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,153 @@
+use std::fmt::Write;
+
+use crate::image::{hex_bytes, Image, ImageError};
+
+const RECORD_LEN: usize = 16;
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT: u8 = 0x02;
+const START_SEGMENT: u8 = 0x03;
+const EXTENDED_LINEAR: u8 = 0x04;
+const START_LINEAR: u8 = 0x05;
+
+fn record(out: &mut String, kind: u8, address: u16, data: &[u8]) {
+    let mut checksum = (data.len() as u8)
+        .wrapping_add((address >> 8) as u8)
+        .wrapping_add(address as u8)
+        .wrapping_add(kind);
+
+    write!(out, ":{:02X}{:04X}{:02X}", data.len(), address, kind).unwrap();
+
+    for &byte in data {
+        checksum = checksum.wrapping_add(byte);
+        write!(out, "{:02X}", byte).unwrap();
+    }
+
+    writeln!(out, "{:02X}", checksum.wrapping_neg()).unwrap();
+}
+
+/// Encodes `image` as Intel HEX, with its start address, if it has one, in a
+/// start segment address record.
+pub fn encode(image: &Image) -> String {
+    let mut out = String::new();
+
+    for segment in &image.segments {
+        for (i, chunk) in segment.data.chunks(RECORD_LEN).enumerate() {
+            let address = segment.address.wrapping_add((i * RECORD_LEN) as u16);
+            record(&mut out, DATA, address, chunk);
+        }
+    }
+
+    if let Some(start) = image.start {
+        record(&mut out, START_SEGMENT, 0, &[0, 0, (start >> 8) as u8, start as u8]);
+    }
+
+    record(&mut out, END_OF_FILE, 0, &[]);
+    out
+}
+
+/// Parses Intel HEX. A start address record, or a non-zero address on the
+/// end of file record as some 8-bit tools write, gives the image's start
+/// address.
+pub fn parse(text: &str) -> Result<Image, ImageError> {
+    let mut image = Image::default();
+
+    for (index, record) in text.lines().enumerate() {
+        let line = index + 1;
+        let record = record.trim();
+
+        if record.is_empty() {
+            continue;
+        }
+
+        let bytes = match record.strip_prefix(':') {
+            Some(digits) => hex_bytes(digits, line)?,
+            None => return Err(ImageError::Syntax { line }),
+        };
+
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(ImageError::Syntax { line });
+        }
+
+        let (body, checksum) = bytes.split_at(bytes.len() - 1);
+        let expected = body.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)).wrapping_neg();
+
+        if checksum[0] != expected {
+            return Err(ImageError::Checksum { line, expected, found: checksum[0] });
+        }
+
+        let address = (body[1] as u16) << 8 | body[2] as u16;
+        let data = &body[4..];
+
+        match body[3] {
+            DATA => {
+                if address as usize + data.len() > 0x10000 {
+                    return Err(ImageError::OutOfRange { line });
+                }
+
+                image.push(address, data);
+            }
+            END_OF_FILE => {
+                if address != 0 {
+                    image.start = Some(address);
+                }
+
+                return Ok(image);
+            }
+            // Only the low 64K is addressable, so a base must be zero.
+            EXTENDED_SEGMENT | EXTENDED_LINEAR if data.iter().all(|&byte| byte == 0) => {}
+            EXTENDED_SEGMENT | EXTENDED_LINEAR => return Err(ImageError::OutOfRange { line }),
+            START_SEGMENT | START_LINEAR if data.len() == 4 => {
+                image.start = Some((data[2] as u16) << 8 | data[3] as u16);
+            }
+            kind => {
+                return Err(ImageError::UnsupportedRecord { line, record: format!("{:02X}", kind) })
+            }
+        }
+    }
+
+    Err(ImageError::MissingEnd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_records() {
+        let mut image = Image::new(0x100, (0..18).collect());
+        image.start = Some(0x100);
+
+        assert_eq!(encode(&image), "\
+            :10010000000102030405060708090A0B0C0D0E0F77\n\
+            :020110001011CC\n\
+            :0400000300000100F8\n\
+            :00000001FF\n");
+        assert_eq!(encode(&Image::default()), ":00000001FF\n");
+    }
+
+    #[test]
+    fn parse_round_trips() {
+        let mut image = Image::new(0x100, (0..40).collect());
+        image.push(0x2000, &[0xff; 3]);
+        image.start = Some(0x100);
+
+        assert_eq!(parse(&encode(&image)), Ok(image.clone()));
+
+        image.start = Some(0);
+        assert_eq!(parse(&encode(&image)), Ok(image));
+        assert_eq!(parse(":00010001FE\n").unwrap().start, Some(0x100));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse(":0100000001FF\n"),
+                   Err(ImageError::Checksum { line: 1, expected: 0xfe, found: 0xff }));
+        assert_eq!(parse(":0100000001FE\n"), Err(ImageError::MissingEnd));
+        assert_eq!(parse("\n0100000001FE"), Err(ImageError::Syntax { line: 2 }));
+        assert_eq!(parse(":02000004000AF0"), Err(ImageError::OutOfRange { line: 1 }));
+        assert_eq!(parse(":00000006FA"),
+                   Err(ImageError::UnsupportedRecord { line: 1, record: "06".into() }));
+    }
+}
@@ -0,0 +1,138 @@
+use std::error;
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+
+use crate::bus::Bus;
+
+/// A contiguous run of bytes at an address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub address: u16,
+    pub data: Vec<u8>,
+}
+
+/// A program image as read from or written to a file format such as Intel
+/// HEX, with an optional entry point.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+    pub start: Option<u16>,
+}
+
+impl Image {
+    pub fn new(address: u16, data: Vec<u8>) -> Self {
+        Image { segments: vec![Segment { address, data }], start: None }
+    }
+
+    /// Copies `range` out of `bus` without side effects.
+    pub fn dump<B: Bus, R: RangeBounds<u16>>(bus: &B, range: R) -> Self {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start as u32,
+            Bound::Excluded(&start) => start as u32 + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end as u32 + 1,
+            Bound::Excluded(&end) => end as u32,
+            Bound::Unbounded => 0x10000,
+        };
+
+        let data = (start..end.max(start)).map(|address| bus.peek(address as u16)).collect();
+        Image::new(start as u16, data)
+    }
+
+    /// Writes every segment to `bus`. Use `Cpu::load_image` to also jump to
+    /// the start address.
+    pub fn load<B: Bus>(&self, bus: &mut B) {
+        for segment in &self.segments {
+            bus.load(&segment.data, segment.address);
+        }
+    }
+
+    /// Appends data, extending the last segment when `address` follows it.
+    pub(crate) fn push(&mut self, address: u16, data: &[u8]) {
+        match self.segments.last_mut() {
+            Some(last) if last.address as usize + last.data.len() == address as usize => {
+                last.data.extend_from_slice(data);
+            }
+            _ => self.segments.push(Segment { address, data: data.to_vec() }),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImageError {
+    /// A record that isn't well formed.
+    Syntax { line: usize },
+    Checksum { line: usize, expected: u8, found: u8 },
+    UnsupportedRecord { line: usize, record: String },
+    /// Data or an address beyond the 8080's 64K address space.
+    OutOfRange { line: usize },
+    /// The input ended without an end of file or termination record.
+    MissingEnd,
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImageError::Syntax { line } => write!(f, "line {}: malformed record", line),
+            ImageError::Checksum { line, expected, found } => {
+                write!(f, "line {}: checksum is {:02X}, expected {:02X}", line, found, expected)
+            }
+            ImageError::UnsupportedRecord { line, ref record } => {
+                write!(f, "line {}: unsupported record type {}", line, record)
+            }
+            ImageError::OutOfRange { line } => {
+                write!(f, "line {}: address out of range", line)
+            }
+            ImageError::MissingEnd => write!(f, "missing end of file record"),
+        }
+    }
+}
+
+impl error::Error for ImageError {}
+
+/// Decodes pairs of hex digits.
+pub(crate) fn hex_bytes(text: &str, line: usize) -> Result<Vec<u8>, ImageError> {
+    if text.len() & 1 != 0 || !text.is_ascii() {
+        return Err(ImageError::Syntax { line });
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(|_| ImageError::Syntax { line }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Memory;
+
+    #[test]
+    fn dump_and_load() {
+        let mut memory = Memory::default();
+        memory.load(&[1, 2, 3, 4], 0x1fff);
+        memory.map_rom(0x0000..0x2000);
+
+        let image = Image::dump(&memory, 0x1fff..=0x2001);
+        assert_eq!(image, Image::new(0x1fff, vec![1, 2, 3]));
+
+        let mut memory = Memory::default();
+        image.load(&mut memory);
+        assert_eq!(memory[0x1fff..0x2003], [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn push_merges_contiguous_data() {
+        let mut image = Image::default();
+        image.push(0x100, &[1, 2]);
+        image.push(0x102, &[3]);
+        image.push(0x200, &[4]);
+
+        assert_eq!(image.segments, [
+            Segment { address: 0x100, data: vec![1, 2, 3] },
+            Segment { address: 0x200, data: vec![4] },
+        ]);
+    }
+}
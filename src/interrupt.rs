@@ -0,0 +1,50 @@
+use crate::opcode::Opcode;
+
+/// An instruction placed on the data bus by an interrupting device during
+/// INTA. Usually a single byte RST, though devices such as the 8259 supply a
+/// three byte CALL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Interrupt {
+    bytes: [u8; 3],
+}
+
+impl Interrupt {
+    /// `RST n` for `n` in `0..8`.
+    pub fn rst(n: u8) -> Self {
+        Interrupt::from(0xc7 | ((n & 0x7) << 3))
+    }
+
+    pub fn call(address: u16) -> Self {
+        Interrupt::from([0xcd, address as u8, (address >> 8) as u8])
+    }
+
+    pub fn opcode(&self) -> Opcode {
+        Opcode::from(self.bytes[0])
+    }
+
+    pub fn operands(&self) -> [u8; 2] {
+        [self.bytes[1], self.bytes[2]]
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.opcode().size() as usize]
+    }
+}
+
+impl From<u8> for Interrupt {
+    fn from(opcode: u8) -> Self {
+        Interrupt { bytes: [opcode, 0, 0] }
+    }
+}
+
+impl From<[u8; 2]> for Interrupt {
+    fn from(bytes: [u8; 2]) -> Self {
+        Interrupt { bytes: [bytes[0], bytes[1], 0] }
+    }
+}
+
+impl From<[u8; 3]> for Interrupt {
+    fn from(bytes: [u8; 3]) -> Self {
+        Interrupt { bytes }
+    }
+}
@@ -1,11 +1,34 @@
+pub mod asm;
+mod bus;
 mod condition_codes;
+pub mod console;
+pub mod cpm;
 mod cpu;
+mod debugger;
+pub mod disasm;
+pub mod gdb;
+pub mod ihex;
+mod image;
+mod interrupt;
 mod memory;
 mod num_impls;
+mod observer;
 mod opcode;
+mod pic;
 mod pointer;
 mod register;
+pub mod rewind;
+pub mod srec;
+pub mod state;
+pub mod trace;
 
+pub use bus::Bus;
+pub use image::{Image, ImageError, Segment};
 pub use cpu::*;
-pub use opcode::Opcode;
+pub use debugger::{Debugger, StopReason};
+pub use interrupt::Interrupt;
+pub use memory::Memory;
+pub use observer::{Access, Observer};
+pub use opcode::{Flags, Opcode, OpcodeInfo, Operand, Pair, Reg, OPCODES};
+pub use pic::Pic8259;
 
@@ -1,44 +1,156 @@
-use std::ops::{Index, Range, RangeFrom};
+use std::ops::{Bound, Index, Range, RangeBounds, RangeFrom};
+use crate::bus::Bus;
 use crate::pointer::Pointer;
 
 pub struct Memory {
     pub memory: Vec<u8>,
-    pub ram_mirror: Option<u16>,
+    regions: Vec<Region>,
 }
 
-macro_rules! index_impl {
-    (&$this:ident, $index:ident) => {
-        match $this.ram_mirror {
-            Some(mirror) if $index as u16 > mirror => {
-                &$this.memory[((($index as u16) % mirror) + mirror) as usize]
-            }
-            _ => &$this.memory[$index as usize]
-        }
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Ram,
+    Rom,
+    Mirror { start: u16, len: u32 },
+    Unmapped(u8),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Region {
+    start: u16,
+    end: u16,
+    kind: Kind,
+}
+
+enum Access<'a> {
+    Ram(usize),
+    Rom(usize),
+    Open(&'a u8),
+}
+
+fn bounds<R: RangeBounds<u16>>(range: &R) -> Option<(u16, u16)> {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start as u32,
+        Bound::Excluded(&start) => start as u32 + 1,
+        Bound::Unbounded => 0,
+    };
+
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end as i32,
+        Bound::Excluded(&end) => end as i32 - 1,
+        Bound::Unbounded => 0xffff,
+    };
+
+    if end < 0 || start > end as u32 {
+        None
+    } else {
+        Some((start as u16, end as u16))
     }
 }
 
+// Regions are consulted newest first, so later mappings take precedence over
+// earlier ones. Addresses not covered by any region behave as RAM.
 impl Memory {
     pub fn load(&mut self, block: &[u8], position: u16) {
-        for (byte, pos) in block.iter().zip(position..) {
-            self.memory[pos as usize] = *byte;
+        for (offset, byte) in block.iter().enumerate() {
+            self.memory[position.wrapping_add(offset as u16) as usize] = *byte;
+        }
+    }
+
+    pub fn map_ram<R: RangeBounds<u16>>(&mut self, range: R) -> &mut Self {
+        self.map(range, Kind::Ram)
+    }
+
+    pub fn map_rom<R: RangeBounds<u16>>(&mut self, range: R) -> &mut Self {
+        self.map(range, Kind::Rom)
+    }
+
+    /// Redirects every access in `range` to `target`, wrapping around when
+    /// `range` is larger than `target`. The redirected address takes on the
+    /// ROM, RAM or unmapped semantics of `target`.
+    pub fn mirror<R, T>(&mut self, range: R, target: T) -> &mut Self
+        where R: RangeBounds<u16>,
+              T: RangeBounds<u16>,
+    {
+        match bounds(&target) {
+            Some((start, end)) => {
+                let len = (end - start) as u32 + 1;
+                self.map(range, Kind::Mirror { start, len })
+            }
+            None => self,
         }
     }
 
+    /// Disconnects `range`; writes are ignored and reads return
+    /// `open_bus_value`.
+    pub fn unmapped<R: RangeBounds<u16>>(&mut self, range: R, open_bus_value: u8) -> &mut Self {
+        self.map(range, Kind::Unmapped(open_bus_value))
+    }
+
     pub fn write<A: Into<u16>, B: Into<u8>>(&mut self, address: A, value: B) {
-        let address = address.into();
         let value = value.into();
-        match self.ram_mirror {
-            Some(mirror) if address >= mirror || address < 0x2000 => {},
-            _ => self.memory[address as usize] = value,
+        if let Access::Ram(index) = self.resolve(address.into()) {
+            self.memory[index] = value;
+        }
+    }
+
+    fn map<R: RangeBounds<u16>>(&mut self, range: R, kind: Kind) -> &mut Self {
+        if let Some((start, end)) = bounds(&range) {
+            self.regions.push(Region { start, end, kind });
+        }
+
+        self
+    }
+
+    fn resolve(&self, address: u16) -> Access<'_> {
+        match self.find(address, true) {
+            // Mirrors are resolved once; a mirror pointing into another mirror
+            // sees the regions underneath it.
+            Some(&Region { start: base, kind: Kind::Mirror { start, len }, .. }) => {
+                let offset = (address - base) as u32 % len;
+                let address = (start as u32 + offset) as u16;
+                Self::access(address, self.find(address, false))
+            }
+            region => Self::access(address, region),
+        }
+    }
+
+    fn find(&self, address: u16, mirrors: bool) -> Option<&Region> {
+        self.regions.iter().rev().find(|region| {
+            address >= region.start &&
+            address <= region.end &&
+            (mirrors || !matches!(region.kind, Kind::Mirror { .. }))
+        })
+    }
+
+    fn access(address: u16, region: Option<&Region>) -> Access<'_> {
+        match region.map(|region| &region.kind) {
+            Some(Kind::Rom) => Access::Rom(address as usize),
+            Some(Kind::Unmapped(value)) => Access::Open(value),
+            _ => Access::Ram(address as usize),
         }
     }
 }
 
+impl Bus for Memory {
+    fn peek(&self, address: u16) -> u8 {
+        self[address]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        Memory::write(self, address, value);
+    }
+
+    fn load(&mut self, block: &[u8], position: u16) {
+        Memory::load(self, block, position);
+    }
+}
+
 impl Default for Memory {
     fn default() -> Self {
         Memory {
             memory: vec![0; 0x10000],
-            ram_mirror: None,
+            regions: Vec::new(),
         }
     }
 }
@@ -67,13 +179,98 @@ impl Index<Pointer> for Memory {
 impl Index<u8> for Memory {
     type Output = u8;
     fn index(&self, index: u8) -> &Self::Output {
-        index_impl!(&self, index)
+        &self[index as u16]
     }
 }
 
 impl Index<u16> for Memory {
     type Output = u8;
     fn index(&self, index: u16) -> &Self::Output {
-        index_impl!(&self, index)
+        match self.resolve(index) {
+            Access::Ram(index) | Access::Rom(index) => &self.memory[index],
+            Access::Open(value) => value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invaders() -> Memory {
+        let mut memory = Memory::default();
+        memory.map_rom(0x0000..0x2000)
+              .map_ram(0x2000..0x4000)
+              .mirror(0x4000..=0xffff, 0x2000..0x4000);
+        memory
+    }
+
+    #[test]
+    fn unmapped_memory_is_ram() {
+        let mut memory = Memory::default();
+
+        memory.write(0u16, 1u8);
+        memory.write(0xffffu16, 2u8);
+
+        assert_eq!(memory[0u16], 1);
+        assert_eq!(memory[0xffffu16], 2);
+    }
+
+    #[test]
+    fn rom_ignores_writes() {
+        let mut memory = invaders();
+        memory.load(&[0xc3], 0x100);
+
+        memory.write(0x100u16, 0u8);
+
+        assert_eq!(memory[0x100u16], 0xc3);
+    }
+
+    #[test]
+    fn mirror_reads_and_writes_agree() {
+        let mut memory = invaders();
+
+        memory.write(0x4010u16, 7u8);
+        assert_eq!(memory[0x2010u16], 7);
+        assert_eq!(memory[0x6010u16], 7);
+
+        memory.write(0x2020u16, 9u8);
+        assert_eq!(memory[0xe020u16], 9);
+    }
+
+    #[test]
+    fn mirror_of_rom_is_read_only() {
+        let mut memory = Memory::default();
+        memory.map_rom(0x0000..0x0800).mirror(0x0800..0x1000, 0x0000..0x0800);
+        memory.load(&[0xaa], 0x10);
+
+        memory.write(0x0810u16, 0u8);
+
+        assert_eq!(memory[0x0810u16], 0xaa);
+        assert_eq!(memory[0x10u16], 0xaa);
+    }
+
+    #[test]
+    fn unmapped_returns_open_bus() {
+        let mut memory = Memory::default();
+        memory.unmapped(0x8000.., 0xff);
+
+        memory.write(0x8000u16, 0u8);
+
+        assert_eq!(memory[0x8000u16], 0xff);
+        assert_eq!(memory.peek(0xffff), 0xff);
+        assert_eq!(memory.memory[0x8000], 0);
+    }
+
+    #[test]
+    fn later_regions_take_precedence() {
+        let mut memory = Memory::default();
+        memory.map_rom(..).map_ram(0x2000..0x2100);
+
+        memory.write(0x2000u16, 1u8);
+        memory.write(0x1fffu16, 1u8);
+
+        assert_eq!(memory[0x2000u16], 1);
+        assert_eq!(memory[0x1fffu16], 0);
     }
 }
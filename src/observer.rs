@@ -0,0 +1,31 @@
+use crate::interrupt::Interrupt;
+use crate::opcode::Opcode;
+
+/// Whether a memory or I/O access reads from or writes to the target.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Callbacks made by `Cpu::emulate` as it runs, for debuggers, tracers and
+/// other tools. Every method does nothing by default, and the `()` observer
+/// used by a plain `Cpu` compiles away entirely.
+pub trait Observer {
+    /// Called once the opcode at `pc` has been fetched, before it runs.
+    fn on_fetch(&mut self, _pc: u16, _opcode: Opcode) {}
+
+    /// Called for every data read made by an instruction. Opcode and operand
+    /// fetches aren't reported.
+    fn on_mem_read(&mut self, _address: u16, _value: u8) {}
+
+    fn on_mem_write(&mut self, _address: u16, _value: u8) {}
+
+    /// Called for IN and OUT with the byte transferred.
+    fn on_io(&mut self, _port: u8, _value: u8, _access: Access) {}
+
+    /// Called when the CPU accepts `interrupt` with PC at `pc`.
+    fn on_interrupt(&mut self, _pc: u16, _interrupt: Interrupt) {}
+}
+
+impl Observer for () {}
@@ -1,52 +1,412 @@
 use std::fmt;
-use std::ops::{Deref, DerefMut};
-
-const CYCLES: [u8; 256] = [
-    4, 10, 7, 5, 5, 5, 7, 4, 4, 10, 7, 5, 5, 5, 7, 4, //0x00..0x0f
-    4, 10, 7, 5, 5, 5, 7, 4, 4, 10, 7, 5, 5, 5, 7, 4, //0x10..0x1f
-    4, 10, 16, 5, 5, 5, 7, 4, 4, 10, 16, 5, 5, 5, 7, 4, //etc
-    4, 10, 13, 5, 10, 10, 10, 4, 4, 10, 13, 5, 5, 5, 7, 4,
-
-    5, 5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5, //0x40..0x4f
-    5, 5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5,
-    5, 5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5,
-    7, 7, 7, 7, 7, 7, 7, 7, 5, 5, 5, 5, 5, 5, 7, 5,
-
-    4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, //0x80..8x4f
-    4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4,
-    4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4,
-    4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4,
-
-    11, 10, 10, 10, 17, 11, 7, 11, 11, 10, 10, 10, 10, 17, 7, 11, //0xc0..0xcf
-    11, 10, 10, 10, 17, 11, 7, 11, 11, 10, 10, 10, 10, 17, 7, 11,
-    11, 10, 10, 18, 17, 11, 7, 11, 11, 5, 10, 5, 17, 17, 7, 11,
-    11, 10, 10, 4, 17, 11, 7, 11, 11, 5, 10, 4, 17, 17, 7, 11,
+use std::ops::{BitOr, Deref, DerefMut};
+
+use self::Operand::{Addr, D16, D8, Pair as RP, Port, Reg as R, Vector};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Reg {
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    /// The memory byte addressed by HL.
+    M,
+    A,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Pair {
+    B,
+    D,
+    H,
+    Sp,
+    Psw,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Operand {
+    Reg(Reg),
+    Pair(Pair),
+    /// An immediate byte.
+    D8,
+    /// An immediate word.
+    D16,
+    /// A 16-bit memory or branch address.
+    Addr,
+    /// An 8-bit I/O port number.
+    Port,
+    /// A restart vector, 0-7.
+    Vector(u8),
+}
+
+/// A set of condition flags, using the bit positions of the flag word.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Flags(u8);
+
+impl Flags {
+    pub const NONE: Flags = Flags(0);
+    pub const CY: Flags = Flags(0x01);
+    pub const P: Flags = Flags(0x04);
+    pub const AC: Flags = Flags(0x10);
+    pub const Z: Flags = Flags(0x40);
+    pub const S: Flags = Flags(0x80);
+    pub const ALL: Flags = Flags(0xd5);
+
+    pub const fn union(self, other: Flags) -> Flags {
+        Flags(self.0 | other.0)
+    }
+
+    pub fn contains(self, other: Flags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    pub mnemonic: &'static str,
+    pub operands: &'static [Operand],
+    pub size: u8,
+    /// Cycles taken by a conditional call or return whose condition fails.
+    pub min_cycles: u8,
+    pub max_cycles: u8,
+    pub flags_read: Flags,
+    pub flags_written: Flags,
+    /// An undocumented alias of another instruction.
+    pub undocumented: bool,
+}
+
+const NONE: Flags = Flags::NONE;
+const ALL: Flags = Flags::ALL;
+const CY: Flags = Flags::CY;
+const P: Flags = Flags::P;
+const Z: Flags = Flags::Z;
+const S: Flags = Flags::S;
+const SZAP: Flags = Flags::S.union(Flags::Z).union(Flags::AC).union(Flags::P);
+const AC_CY: Flags = Flags::AC.union(Flags::CY);
+
+const fn op(
+    mnemonic: &'static str,
+    operands: &'static [Operand],
+    size: u8,
+    min_cycles: u8,
+    max_cycles: u8,
+    flags_read: Flags,
+    flags_written: Flags,
+) -> OpcodeInfo {
+    OpcodeInfo {
+        mnemonic,
+        operands,
+        size,
+        min_cycles,
+        max_cycles,
+        flags_read,
+        flags_written,
+        undocumented: false,
+    }
+}
+
+const fn alt(
+    mnemonic: &'static str,
+    operands: &'static [Operand],
+    size: u8,
+    min_cycles: u8,
+    max_cycles: u8,
+    flags_read: Flags,
+    flags_written: Flags,
+) -> OpcodeInfo {
+    let mut info = op(mnemonic, operands, size, min_cycles, max_cycles, flags_read, flags_written);
+    info.undocumented = true;
+    info
+}
+
+pub static OPCODES: [OpcodeInfo; 256] = [
+    op("NOP", &[], 1, 4, 4, NONE, NONE), // 0x00
+    op("LXI", &[RP(Pair::B), D16], 3, 10, 10, NONE, NONE), // 0x01
+    op("STAX", &[RP(Pair::B)], 1, 7, 7, NONE, NONE), // 0x02
+    op("INX", &[RP(Pair::B)], 1, 5, 5, NONE, NONE), // 0x03
+    op("INR", &[R(Reg::B)], 1, 5, 5, NONE, SZAP), // 0x04
+    op("DCR", &[R(Reg::B)], 1, 5, 5, NONE, SZAP), // 0x05
+    op("MVI", &[R(Reg::B), D8], 2, 7, 7, NONE, NONE), // 0x06
+    op("RLC", &[], 1, 4, 4, NONE, CY), // 0x07
+    alt("NOP", &[], 1, 4, 4, NONE, NONE), // 0x08
+    op("DAD", &[RP(Pair::B)], 1, 10, 10, NONE, CY), // 0x09
+    op("LDAX", &[RP(Pair::B)], 1, 7, 7, NONE, NONE), // 0x0a
+    op("DCX", &[RP(Pair::B)], 1, 5, 5, NONE, NONE), // 0x0b
+    op("INR", &[R(Reg::C)], 1, 5, 5, NONE, SZAP), // 0x0c
+    op("DCR", &[R(Reg::C)], 1, 5, 5, NONE, SZAP), // 0x0d
+    op("MVI", &[R(Reg::C), D8], 2, 7, 7, NONE, NONE), // 0x0e
+    op("RRC", &[], 1, 4, 4, NONE, CY), // 0x0f
+    alt("NOP", &[], 1, 4, 4, NONE, NONE), // 0x10
+    op("LXI", &[RP(Pair::D), D16], 3, 10, 10, NONE, NONE), // 0x11
+    op("STAX", &[RP(Pair::D)], 1, 7, 7, NONE, NONE), // 0x12
+    op("INX", &[RP(Pair::D)], 1, 5, 5, NONE, NONE), // 0x13
+    op("INR", &[R(Reg::D)], 1, 5, 5, NONE, SZAP), // 0x14
+    op("DCR", &[R(Reg::D)], 1, 5, 5, NONE, SZAP), // 0x15
+    op("MVI", &[R(Reg::D), D8], 2, 7, 7, NONE, NONE), // 0x16
+    op("RAL", &[], 1, 4, 4, CY, CY), // 0x17
+    alt("NOP", &[], 1, 4, 4, NONE, NONE), // 0x18
+    op("DAD", &[RP(Pair::D)], 1, 10, 10, NONE, CY), // 0x19
+    op("LDAX", &[RP(Pair::D)], 1, 7, 7, NONE, NONE), // 0x1a
+    op("DCX", &[RP(Pair::D)], 1, 5, 5, NONE, NONE), // 0x1b
+    op("INR", &[R(Reg::E)], 1, 5, 5, NONE, SZAP), // 0x1c
+    op("DCR", &[R(Reg::E)], 1, 5, 5, NONE, SZAP), // 0x1d
+    op("MVI", &[R(Reg::E), D8], 2, 7, 7, NONE, NONE), // 0x1e
+    op("RAR", &[], 1, 4, 4, CY, CY), // 0x1f
+    alt("NOP", &[], 1, 4, 4, NONE, NONE), // 0x20
+    op("LXI", &[RP(Pair::H), D16], 3, 10, 10, NONE, NONE), // 0x21
+    op("SHLD", &[Addr], 3, 16, 16, NONE, NONE), // 0x22
+    op("INX", &[RP(Pair::H)], 1, 5, 5, NONE, NONE), // 0x23
+    op("INR", &[R(Reg::H)], 1, 5, 5, NONE, SZAP), // 0x24
+    op("DCR", &[R(Reg::H)], 1, 5, 5, NONE, SZAP), // 0x25
+    op("MVI", &[R(Reg::H), D8], 2, 7, 7, NONE, NONE), // 0x26
+    op("DAA", &[], 1, 4, 4, AC_CY, ALL), // 0x27
+    alt("NOP", &[], 1, 4, 4, NONE, NONE), // 0x28
+    op("DAD", &[RP(Pair::H)], 1, 10, 10, NONE, CY), // 0x29
+    op("LHLD", &[Addr], 3, 16, 16, NONE, NONE), // 0x2a
+    op("DCX", &[RP(Pair::H)], 1, 5, 5, NONE, NONE), // 0x2b
+    op("INR", &[R(Reg::L)], 1, 5, 5, NONE, SZAP), // 0x2c
+    op("DCR", &[R(Reg::L)], 1, 5, 5, NONE, SZAP), // 0x2d
+    op("MVI", &[R(Reg::L), D8], 2, 7, 7, NONE, NONE), // 0x2e
+    op("CMA", &[], 1, 4, 4, NONE, NONE), // 0x2f
+    alt("NOP", &[], 1, 4, 4, NONE, NONE), // 0x30
+    op("LXI", &[RP(Pair::Sp), D16], 3, 10, 10, NONE, NONE), // 0x31
+    op("STA", &[Addr], 3, 13, 13, NONE, NONE), // 0x32
+    op("INX", &[RP(Pair::Sp)], 1, 5, 5, NONE, NONE), // 0x33
+    op("INR", &[R(Reg::M)], 1, 10, 10, NONE, SZAP), // 0x34
+    op("DCR", &[R(Reg::M)], 1, 10, 10, NONE, SZAP), // 0x35
+    op("MVI", &[R(Reg::M), D8], 2, 10, 10, NONE, NONE), // 0x36
+    op("STC", &[], 1, 4, 4, NONE, CY), // 0x37
+    alt("NOP", &[], 1, 4, 4, NONE, NONE), // 0x38
+    op("DAD", &[RP(Pair::Sp)], 1, 10, 10, NONE, CY), // 0x39
+    op("LDA", &[Addr], 3, 13, 13, NONE, NONE), // 0x3a
+    op("DCX", &[RP(Pair::Sp)], 1, 5, 5, NONE, NONE), // 0x3b
+    op("INR", &[R(Reg::A)], 1, 5, 5, NONE, SZAP), // 0x3c
+    op("DCR", &[R(Reg::A)], 1, 5, 5, NONE, SZAP), // 0x3d
+    op("MVI", &[R(Reg::A), D8], 2, 7, 7, NONE, NONE), // 0x3e
+    op("CMC", &[], 1, 4, 4, CY, CY), // 0x3f
+    op("MOV", &[R(Reg::B), R(Reg::B)], 1, 5, 5, NONE, NONE), // 0x40
+    op("MOV", &[R(Reg::B), R(Reg::C)], 1, 5, 5, NONE, NONE), // 0x41
+    op("MOV", &[R(Reg::B), R(Reg::D)], 1, 5, 5, NONE, NONE), // 0x42
+    op("MOV", &[R(Reg::B), R(Reg::E)], 1, 5, 5, NONE, NONE), // 0x43
+    op("MOV", &[R(Reg::B), R(Reg::H)], 1, 5, 5, NONE, NONE), // 0x44
+    op("MOV", &[R(Reg::B), R(Reg::L)], 1, 5, 5, NONE, NONE), // 0x45
+    op("MOV", &[R(Reg::B), R(Reg::M)], 1, 7, 7, NONE, NONE), // 0x46
+    op("MOV", &[R(Reg::B), R(Reg::A)], 1, 5, 5, NONE, NONE), // 0x47
+    op("MOV", &[R(Reg::C), R(Reg::B)], 1, 5, 5, NONE, NONE), // 0x48
+    op("MOV", &[R(Reg::C), R(Reg::C)], 1, 5, 5, NONE, NONE), // 0x49
+    op("MOV", &[R(Reg::C), R(Reg::D)], 1, 5, 5, NONE, NONE), // 0x4a
+    op("MOV", &[R(Reg::C), R(Reg::E)], 1, 5, 5, NONE, NONE), // 0x4b
+    op("MOV", &[R(Reg::C), R(Reg::H)], 1, 5, 5, NONE, NONE), // 0x4c
+    op("MOV", &[R(Reg::C), R(Reg::L)], 1, 5, 5, NONE, NONE), // 0x4d
+    op("MOV", &[R(Reg::C), R(Reg::M)], 1, 7, 7, NONE, NONE), // 0x4e
+    op("MOV", &[R(Reg::C), R(Reg::A)], 1, 5, 5, NONE, NONE), // 0x4f
+    op("MOV", &[R(Reg::D), R(Reg::B)], 1, 5, 5, NONE, NONE), // 0x50
+    op("MOV", &[R(Reg::D), R(Reg::C)], 1, 5, 5, NONE, NONE), // 0x51
+    op("MOV", &[R(Reg::D), R(Reg::D)], 1, 5, 5, NONE, NONE), // 0x52
+    op("MOV", &[R(Reg::D), R(Reg::E)], 1, 5, 5, NONE, NONE), // 0x53
+    op("MOV", &[R(Reg::D), R(Reg::H)], 1, 5, 5, NONE, NONE), // 0x54
+    op("MOV", &[R(Reg::D), R(Reg::L)], 1, 5, 5, NONE, NONE), // 0x55
+    op("MOV", &[R(Reg::D), R(Reg::M)], 1, 7, 7, NONE, NONE), // 0x56
+    op("MOV", &[R(Reg::D), R(Reg::A)], 1, 5, 5, NONE, NONE), // 0x57
+    op("MOV", &[R(Reg::E), R(Reg::B)], 1, 5, 5, NONE, NONE), // 0x58
+    op("MOV", &[R(Reg::E), R(Reg::C)], 1, 5, 5, NONE, NONE), // 0x59
+    op("MOV", &[R(Reg::E), R(Reg::D)], 1, 5, 5, NONE, NONE), // 0x5a
+    op("MOV", &[R(Reg::E), R(Reg::E)], 1, 5, 5, NONE, NONE), // 0x5b
+    op("MOV", &[R(Reg::E), R(Reg::H)], 1, 5, 5, NONE, NONE), // 0x5c
+    op("MOV", &[R(Reg::E), R(Reg::L)], 1, 5, 5, NONE, NONE), // 0x5d
+    op("MOV", &[R(Reg::E), R(Reg::M)], 1, 7, 7, NONE, NONE), // 0x5e
+    op("MOV", &[R(Reg::E), R(Reg::A)], 1, 5, 5, NONE, NONE), // 0x5f
+    op("MOV", &[R(Reg::H), R(Reg::B)], 1, 5, 5, NONE, NONE), // 0x60
+    op("MOV", &[R(Reg::H), R(Reg::C)], 1, 5, 5, NONE, NONE), // 0x61
+    op("MOV", &[R(Reg::H), R(Reg::D)], 1, 5, 5, NONE, NONE), // 0x62
+    op("MOV", &[R(Reg::H), R(Reg::E)], 1, 5, 5, NONE, NONE), // 0x63
+    op("MOV", &[R(Reg::H), R(Reg::H)], 1, 5, 5, NONE, NONE), // 0x64
+    op("MOV", &[R(Reg::H), R(Reg::L)], 1, 5, 5, NONE, NONE), // 0x65
+    op("MOV", &[R(Reg::H), R(Reg::M)], 1, 7, 7, NONE, NONE), // 0x66
+    op("MOV", &[R(Reg::H), R(Reg::A)], 1, 5, 5, NONE, NONE), // 0x67
+    op("MOV", &[R(Reg::L), R(Reg::B)], 1, 5, 5, NONE, NONE), // 0x68
+    op("MOV", &[R(Reg::L), R(Reg::C)], 1, 5, 5, NONE, NONE), // 0x69
+    op("MOV", &[R(Reg::L), R(Reg::D)], 1, 5, 5, NONE, NONE), // 0x6a
+    op("MOV", &[R(Reg::L), R(Reg::E)], 1, 5, 5, NONE, NONE), // 0x6b
+    op("MOV", &[R(Reg::L), R(Reg::H)], 1, 5, 5, NONE, NONE), // 0x6c
+    op("MOV", &[R(Reg::L), R(Reg::L)], 1, 5, 5, NONE, NONE), // 0x6d
+    op("MOV", &[R(Reg::L), R(Reg::M)], 1, 7, 7, NONE, NONE), // 0x6e
+    op("MOV", &[R(Reg::L), R(Reg::A)], 1, 5, 5, NONE, NONE), // 0x6f
+    op("MOV", &[R(Reg::M), R(Reg::B)], 1, 7, 7, NONE, NONE), // 0x70
+    op("MOV", &[R(Reg::M), R(Reg::C)], 1, 7, 7, NONE, NONE), // 0x71
+    op("MOV", &[R(Reg::M), R(Reg::D)], 1, 7, 7, NONE, NONE), // 0x72
+    op("MOV", &[R(Reg::M), R(Reg::E)], 1, 7, 7, NONE, NONE), // 0x73
+    op("MOV", &[R(Reg::M), R(Reg::H)], 1, 7, 7, NONE, NONE), // 0x74
+    op("MOV", &[R(Reg::M), R(Reg::L)], 1, 7, 7, NONE, NONE), // 0x75
+    op("HLT", &[], 1, 7, 7, NONE, NONE), // 0x76
+    op("MOV", &[R(Reg::M), R(Reg::A)], 1, 7, 7, NONE, NONE), // 0x77
+    op("MOV", &[R(Reg::A), R(Reg::B)], 1, 5, 5, NONE, NONE), // 0x78
+    op("MOV", &[R(Reg::A), R(Reg::C)], 1, 5, 5, NONE, NONE), // 0x79
+    op("MOV", &[R(Reg::A), R(Reg::D)], 1, 5, 5, NONE, NONE), // 0x7a
+    op("MOV", &[R(Reg::A), R(Reg::E)], 1, 5, 5, NONE, NONE), // 0x7b
+    op("MOV", &[R(Reg::A), R(Reg::H)], 1, 5, 5, NONE, NONE), // 0x7c
+    op("MOV", &[R(Reg::A), R(Reg::L)], 1, 5, 5, NONE, NONE), // 0x7d
+    op("MOV", &[R(Reg::A), R(Reg::M)], 1, 7, 7, NONE, NONE), // 0x7e
+    op("MOV", &[R(Reg::A), R(Reg::A)], 1, 5, 5, NONE, NONE), // 0x7f
+    op("ADD", &[R(Reg::B)], 1, 4, 4, NONE, ALL), // 0x80
+    op("ADD", &[R(Reg::C)], 1, 4, 4, NONE, ALL), // 0x81
+    op("ADD", &[R(Reg::D)], 1, 4, 4, NONE, ALL), // 0x82
+    op("ADD", &[R(Reg::E)], 1, 4, 4, NONE, ALL), // 0x83
+    op("ADD", &[R(Reg::H)], 1, 4, 4, NONE, ALL), // 0x84
+    op("ADD", &[R(Reg::L)], 1, 4, 4, NONE, ALL), // 0x85
+    op("ADD", &[R(Reg::M)], 1, 7, 7, NONE, ALL), // 0x86
+    op("ADD", &[R(Reg::A)], 1, 4, 4, NONE, ALL), // 0x87
+    op("ADC", &[R(Reg::B)], 1, 4, 4, CY, ALL), // 0x88
+    op("ADC", &[R(Reg::C)], 1, 4, 4, CY, ALL), // 0x89
+    op("ADC", &[R(Reg::D)], 1, 4, 4, CY, ALL), // 0x8a
+    op("ADC", &[R(Reg::E)], 1, 4, 4, CY, ALL), // 0x8b
+    op("ADC", &[R(Reg::H)], 1, 4, 4, CY, ALL), // 0x8c
+    op("ADC", &[R(Reg::L)], 1, 4, 4, CY, ALL), // 0x8d
+    op("ADC", &[R(Reg::M)], 1, 7, 7, CY, ALL), // 0x8e
+    op("ADC", &[R(Reg::A)], 1, 4, 4, CY, ALL), // 0x8f
+    op("SUB", &[R(Reg::B)], 1, 4, 4, NONE, ALL), // 0x90
+    op("SUB", &[R(Reg::C)], 1, 4, 4, NONE, ALL), // 0x91
+    op("SUB", &[R(Reg::D)], 1, 4, 4, NONE, ALL), // 0x92
+    op("SUB", &[R(Reg::E)], 1, 4, 4, NONE, ALL), // 0x93
+    op("SUB", &[R(Reg::H)], 1, 4, 4, NONE, ALL), // 0x94
+    op("SUB", &[R(Reg::L)], 1, 4, 4, NONE, ALL), // 0x95
+    op("SUB", &[R(Reg::M)], 1, 7, 7, NONE, ALL), // 0x96
+    op("SUB", &[R(Reg::A)], 1, 4, 4, NONE, ALL), // 0x97
+    op("SBB", &[R(Reg::B)], 1, 4, 4, CY, ALL), // 0x98
+    op("SBB", &[R(Reg::C)], 1, 4, 4, CY, ALL), // 0x99
+    op("SBB", &[R(Reg::D)], 1, 4, 4, CY, ALL), // 0x9a
+    op("SBB", &[R(Reg::E)], 1, 4, 4, CY, ALL), // 0x9b
+    op("SBB", &[R(Reg::H)], 1, 4, 4, CY, ALL), // 0x9c
+    op("SBB", &[R(Reg::L)], 1, 4, 4, CY, ALL), // 0x9d
+    op("SBB", &[R(Reg::M)], 1, 7, 7, CY, ALL), // 0x9e
+    op("SBB", &[R(Reg::A)], 1, 4, 4, CY, ALL), // 0x9f
+    op("ANA", &[R(Reg::B)], 1, 4, 4, NONE, ALL), // 0xa0
+    op("ANA", &[R(Reg::C)], 1, 4, 4, NONE, ALL), // 0xa1
+    op("ANA", &[R(Reg::D)], 1, 4, 4, NONE, ALL), // 0xa2
+    op("ANA", &[R(Reg::E)], 1, 4, 4, NONE, ALL), // 0xa3
+    op("ANA", &[R(Reg::H)], 1, 4, 4, NONE, ALL), // 0xa4
+    op("ANA", &[R(Reg::L)], 1, 4, 4, NONE, ALL), // 0xa5
+    op("ANA", &[R(Reg::M)], 1, 7, 7, NONE, ALL), // 0xa6
+    op("ANA", &[R(Reg::A)], 1, 4, 4, NONE, ALL), // 0xa7
+    op("XRA", &[R(Reg::B)], 1, 4, 4, NONE, ALL), // 0xa8
+    op("XRA", &[R(Reg::C)], 1, 4, 4, NONE, ALL), // 0xa9
+    op("XRA", &[R(Reg::D)], 1, 4, 4, NONE, ALL), // 0xaa
+    op("XRA", &[R(Reg::E)], 1, 4, 4, NONE, ALL), // 0xab
+    op("XRA", &[R(Reg::H)], 1, 4, 4, NONE, ALL), // 0xac
+    op("XRA", &[R(Reg::L)], 1, 4, 4, NONE, ALL), // 0xad
+    op("XRA", &[R(Reg::M)], 1, 7, 7, NONE, ALL), // 0xae
+    op("XRA", &[R(Reg::A)], 1, 4, 4, NONE, ALL), // 0xaf
+    op("ORA", &[R(Reg::B)], 1, 4, 4, NONE, ALL), // 0xb0
+    op("ORA", &[R(Reg::C)], 1, 4, 4, NONE, ALL), // 0xb1
+    op("ORA", &[R(Reg::D)], 1, 4, 4, NONE, ALL), // 0xb2
+    op("ORA", &[R(Reg::E)], 1, 4, 4, NONE, ALL), // 0xb3
+    op("ORA", &[R(Reg::H)], 1, 4, 4, NONE, ALL), // 0xb4
+    op("ORA", &[R(Reg::L)], 1, 4, 4, NONE, ALL), // 0xb5
+    op("ORA", &[R(Reg::M)], 1, 7, 7, NONE, ALL), // 0xb6
+    op("ORA", &[R(Reg::A)], 1, 4, 4, NONE, ALL), // 0xb7
+    op("CMP", &[R(Reg::B)], 1, 4, 4, NONE, ALL), // 0xb8
+    op("CMP", &[R(Reg::C)], 1, 4, 4, NONE, ALL), // 0xb9
+    op("CMP", &[R(Reg::D)], 1, 4, 4, NONE, ALL), // 0xba
+    op("CMP", &[R(Reg::E)], 1, 4, 4, NONE, ALL), // 0xbb
+    op("CMP", &[R(Reg::H)], 1, 4, 4, NONE, ALL), // 0xbc
+    op("CMP", &[R(Reg::L)], 1, 4, 4, NONE, ALL), // 0xbd
+    op("CMP", &[R(Reg::M)], 1, 7, 7, NONE, ALL), // 0xbe
+    op("CMP", &[R(Reg::A)], 1, 4, 4, NONE, ALL), // 0xbf
+    op("RNZ", &[], 1, 5, 11, Z, NONE), // 0xc0
+    op("POP", &[RP(Pair::B)], 1, 10, 10, NONE, NONE), // 0xc1
+    op("JNZ", &[Addr], 3, 10, 10, Z, NONE), // 0xc2
+    op("JMP", &[Addr], 3, 10, 10, NONE, NONE), // 0xc3
+    op("CNZ", &[Addr], 3, 11, 17, Z, NONE), // 0xc4
+    op("PUSH", &[RP(Pair::B)], 1, 11, 11, NONE, NONE), // 0xc5
+    op("ADI", &[D8], 2, 7, 7, NONE, ALL), // 0xc6
+    op("RST", &[Vector(0)], 1, 11, 11, NONE, NONE), // 0xc7
+    op("RZ", &[], 1, 5, 11, Z, NONE), // 0xc8
+    op("RET", &[], 1, 10, 10, NONE, NONE), // 0xc9
+    op("JZ", &[Addr], 3, 10, 10, Z, NONE), // 0xca
+    alt("JMP", &[Addr], 3, 10, 10, NONE, NONE), // 0xcb
+    op("CZ", &[Addr], 3, 11, 17, Z, NONE), // 0xcc
+    op("CALL", &[Addr], 3, 17, 17, NONE, NONE), // 0xcd
+    op("ACI", &[D8], 2, 7, 7, CY, ALL), // 0xce
+    op("RST", &[Vector(1)], 1, 11, 11, NONE, NONE), // 0xcf
+    op("RNC", &[], 1, 5, 11, CY, NONE), // 0xd0
+    op("POP", &[RP(Pair::D)], 1, 10, 10, NONE, NONE), // 0xd1
+    op("JNC", &[Addr], 3, 10, 10, CY, NONE), // 0xd2
+    op("OUT", &[Port], 2, 10, 10, NONE, NONE), // 0xd3
+    op("CNC", &[Addr], 3, 11, 17, CY, NONE), // 0xd4
+    op("PUSH", &[RP(Pair::D)], 1, 11, 11, NONE, NONE), // 0xd5
+    op("SUI", &[D8], 2, 7, 7, NONE, ALL), // 0xd6
+    op("RST", &[Vector(2)], 1, 11, 11, NONE, NONE), // 0xd7
+    op("RC", &[], 1, 5, 11, CY, NONE), // 0xd8
+    alt("RET", &[], 1, 10, 10, NONE, NONE), // 0xd9
+    op("JC", &[Addr], 3, 10, 10, CY, NONE), // 0xda
+    op("IN", &[Port], 2, 10, 10, NONE, NONE), // 0xdb
+    op("CC", &[Addr], 3, 11, 17, CY, NONE), // 0xdc
+    alt("CALL", &[Addr], 3, 17, 17, NONE, NONE), // 0xdd
+    op("SBI", &[D8], 2, 7, 7, CY, ALL), // 0xde
+    op("RST", &[Vector(3)], 1, 11, 11, NONE, NONE), // 0xdf
+    op("RPO", &[], 1, 5, 11, P, NONE), // 0xe0
+    op("POP", &[RP(Pair::H)], 1, 10, 10, NONE, NONE), // 0xe1
+    op("JPO", &[Addr], 3, 10, 10, P, NONE), // 0xe2
+    op("XTHL", &[], 1, 18, 18, NONE, NONE), // 0xe3
+    op("CPO", &[Addr], 3, 11, 17, P, NONE), // 0xe4
+    op("PUSH", &[RP(Pair::H)], 1, 11, 11, NONE, NONE), // 0xe5
+    op("ANI", &[D8], 2, 7, 7, NONE, ALL), // 0xe6
+    op("RST", &[Vector(4)], 1, 11, 11, NONE, NONE), // 0xe7
+    op("RPE", &[], 1, 5, 11, P, NONE), // 0xe8
+    op("PCHL", &[], 1, 5, 5, NONE, NONE), // 0xe9
+    op("JPE", &[Addr], 3, 10, 10, P, NONE), // 0xea
+    op("XCHG", &[], 1, 5, 5, NONE, NONE), // 0xeb
+    op("CPE", &[Addr], 3, 11, 17, P, NONE), // 0xec
+    alt("CALL", &[Addr], 3, 17, 17, NONE, NONE), // 0xed
+    op("XRI", &[D8], 2, 7, 7, NONE, ALL), // 0xee
+    op("RST", &[Vector(5)], 1, 11, 11, NONE, NONE), // 0xef
+    op("RP", &[], 1, 5, 11, S, NONE), // 0xf0
+    op("POP", &[RP(Pair::Psw)], 1, 10, 10, NONE, ALL), // 0xf1
+    op("JP", &[Addr], 3, 10, 10, S, NONE), // 0xf2
+    op("DI", &[], 1, 4, 4, NONE, NONE), // 0xf3
+    op("CP", &[Addr], 3, 11, 17, S, NONE), // 0xf4
+    op("PUSH", &[RP(Pair::Psw)], 1, 11, 11, ALL, NONE), // 0xf5
+    op("ORI", &[D8], 2, 7, 7, NONE, ALL), // 0xf6
+    op("RST", &[Vector(6)], 1, 11, 11, NONE, NONE), // 0xf7
+    op("RM", &[], 1, 5, 11, S, NONE), // 0xf8
+    op("SPHL", &[], 1, 5, 5, NONE, NONE), // 0xf9
+    op("JM", &[Addr], 3, 10, 10, S, NONE), // 0xfa
+    op("EI", &[], 1, 4, 4, NONE, NONE), // 0xfb
+    op("CM", &[Addr], 3, 11, 17, S, NONE), // 0xfc
+    alt("CALL", &[Addr], 3, 17, 17, NONE, NONE), // 0xfd
+    op("CPI", &[D8], 2, 7, 7, NONE, ALL), // 0xfe
+    op("RST", &[Vector(7)], 1, 11, 11, NONE, NONE), // 0xff
 ];
 
 #[derive(Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Opcode(u8);
 
 impl Opcode {
-    pub fn size(&self) -> u8 {
-        match self.0 {
-            0x01 | 0x11 | 0x21 | 0x22 | 0x2a | 0x31 | 0x32 | 0x3a | 0xc2 |
-            0xc3 | 0xc4 | 0xCA | 0xCC | 0xCD | 0xD2 | 0xD4 | 0xDA | 0xDC |
-            0xE2 | 0xE4 | 0xEA | 0xEC | 0xF2 | 0xF4 | 0xFA | 0xFC | 0xDD |
-            0xED | 0xFD => 3,
-
-            0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E | 0xC6 |
-            0xCE | 0xD3 | 0xDE | 0xD6 | 0xDB | 0xe6 | 0xEE | 0xF6 | 0xFE => 2,
+    pub fn info(&self) -> &'static OpcodeInfo {
+        &OPCODES[self.0 as usize]
+    }
 
-            _ => 1,
-        }
+    pub fn size(&self) -> u8 {
+        self.info().size
     }
 
     pub fn cycle_size(&self) -> u8 {
+        self.info().max_cycles
+    }
 
-        if self.0 == 0xd3 || self.0 == 0xdb {
-            3
+    /// The cycles taken by the instruction, where `taken` is whether a
+    /// conditional call or return had its condition met.
+    pub fn cycles(&self, taken: bool) -> u8 {
+        if taken {
+            self.info().max_cycles
         } else {
-            CYCLES[self.0 as usize]
+            self.info().min_cycles
         }
     }
 }
@@ -72,265 +432,103 @@ impl DerefMut for Opcode {
 
 impl fmt::Debug for Opcode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let op = match self.0 {
-            0x00 => "NOP",
-            0x01 => "LXI B",
-            0x02 => "STAX B",
-            0x03 => "INX B",
-            0x04 => "INR B",
-            0x05 => "DCR B",
-            0x06 => "MVI B",
-            0x07 => "RLC",
-            0x08 => "0x08",
-            0x09 => "DAD B",
-            0x0A => "LDAX B",
-            0x0B => "DCX B",
-            0x0C => "INR C",
-            0x0D => "DCR C",
-            0x0E => "MVI C",
-            0x0F => "RRC",
-            0x10 => "0x10",
-            0x11 => "LXI D",
-            0x12 => "STAX D",
-            0x13 => "INX D",
-            0x14 => "INR D",
-            0x15 => "DCR D",
-            0x16 => "MVI D",
-            0x17 => "RAL",
-            0x18 => "0x18",
-            0x19 => "DAD D",
-            0x1a => "LDAX D",
-            0x1b => "DCX D",
-            0x1c => "INR E",
-            0x1d => "DCR E",
-            0x1e => "MVI E",
-            0x1f => "RAR",
-            0x20 => "0x20",
-            0x21 => "LXI H",
-            0x22 => "SHLD",
-            0x23 => "INX H",
-            0x24 => "INR H",
-            0x25 => "DCR H",
-            0x26 => "MVI H",
-            0x27 => "DAA",
-            0x28 => "0x28",
-            0x29 => "DAD H",
-            0x2a => "LHLD",
-            0x2b => "DCX H",
-            0x2c => "INR L",
-            0x2d => "DCR L",
-            0x2e => "MVI L",
-            0x2f => "CMA",
-            0x30 => "0x30",
-            0x31 => "LXI SP",
-            0x32 => "STA",
-            0x33 => "INX SP",
-            0x34 => "INR M",
-            0x35 => "DCR M",
-            0x36 => "MVI M",
-            0x37 => "STC",
-            0x38 => "0x38",
-            0x39 => "DAD SP",
-            0x3a => "LDA",
-            0x3b => "DCX SP",
-            0x3c => "INR A",
-            0x3d => "DCR A",
-            0x3e => "MVI A",
-            0x3f => "CMC",
-            0x40 => "MOV B, B",
-            0x41 => "MOV B, C",
-            0x42 => "MOV B, D",
-            0x43 => "MOV B, E",
-            0x44 => "MOV B, H",
-            0x45 => "MOV B, L",
-            0x46 => "MOV B, M",
-            0x47 => "MOV B, A",
-            0x48 => "MOV C, B",
-            0x49 => "MOV C, C",
-            0x4a => "MOV C, D",
-            0x4b => "MOV C, E",
-            0x4c => "MOV C, H",
-            0x4d => "MOV C, L",
-            0x4e => "MOV C, M",
-            0x4f => "MOV C, A",
-            0x50 => "MOV D, B",
-            0x51 => "MOV D, C",
-            0x52 => "MOV D, D",
-            0x53 => "MOV D, E",
-            0x54 => "MOV D, H",
-            0x55 => "MOV D, L",
-            0x56 => "MOV D, M",
-            0x57 => "MOV D, A",
-            0x58 => "MOV E, B",
-            0x59 => "MOV E, C",
-            0x5a => "MOV E, D",
-            0x5b => "MOV E, E",
-            0x5c => "MOV E, H",
-            0x5d => "MOV E, L",
-            0x5e => "MOV E, M",
-            0x5f => "MOV E, A",
-            0x60 => "MOV H, B",
-            0x61 => "MOV H, C",
-            0x62 => "MOV H, D",
-            0x63 => "MOV H, E",
-            0x64 => "MOV H, H",
-            0x65 => "MOV H, L",
-            0x66 => "MOV H, M",
-            0x67 => "MOV H, A",
-            0x68 => "MOV L, B",
-            0x69 => "MOV L, C",
-            0x6a => "MOV L, D",
-            0x6b => "MOV L, E",
-            0x6c => "MOV L, H",
-            0x6d => "MOV L, L",
-            0x6e => "MOV L, M",
-            0x6f => "MOV L, A",
-            0x70 => "MOV M, B",
-            0x71 => "MOV M, C",
-            0x72 => "MOV M, D",
-            0x73 => "MOV M, E",
-            0x74 => "MOV M, H",
-            0x75 => "MOV M, L",
-            0x76 => "HLT",
-            0x77 => "MOV M, A",
-            0x78 => "MOV A, B",
-            0x79 => "MOV A, C",
-            0x7a => "MOV A, D",
-            0x7b => "MOV A, E",
-            0x7c => "MOV A, H",
-            0x7d => "MOV A, L",
-            0x7e => "MOV A, M",
-            0x7f => "MOV A, A",
-            0x80 => "ADD B",
-            0x81 => "ADD C",
-            0x82 => "ADD D",
-            0x83 => "ADD E",
-            0x84 => "ADD H",
-            0x85 => "ADD L",
-            0x86 => "ADD M",
-            0x87 => "ADD A",
-            0x88 => "ADC B",
-            0x89 => "ADC C",
-            0x8a => "ADC D",
-            0x8b => "ADC E",
-            0x8c => "ADC H",
-            0x8d => "ADC L",
-            0x8e => "ADC M",
-            0x8f => "ADC A",
-            0x90 => "SUB B",
-            0x91 => "SUB C",
-            0x92 => "SUB D",
-            0x93 => "SUB E",
-            0x94 => "SUB H",
-            0x95 => "SUB L",
-            0x96 => "SUB M",
-            0x97 => "SUB A",
-            0x98 => "SBB B",
-            0x99 => "SBB C",
-            0x9a => "SBB D",
-            0x9b => "SBB E",
-            0x9c => "SBB H",
-            0x9d => "SBB L",
-            0x9e => "SBB M",
-            0x9f => "SBB A",
-            0xa0 => "ANA B",
-            0xa1 => "ANA C",
-            0xa2 => "ANA D",
-            0xa3 => "ANA E",
-            0xa4 => "ANA H",
-            0xa5 => "ANA L",
-            0xa6 => "ANA M",
-            0xa7 => "ANA A",
-            0xa8 => "XRA B",
-            0xa9 => "XRA C",
-            0xaa => "XRA D",
-            0xab => "XRA E",
-            0xac => "XRA H",
-            0xad => "XRA L",
-            0xae => "XRA M",
-            0xaf => "XRA A",
-            0xb0 => "ORA B",
-            0xb1 => "ORA C",
-            0xb2 => "ORA D",
-            0xb3 => "ORA E",
-            0xb4 => "ORA H",
-            0xb5 => "ORA L",
-            0xb6 => "ORA M",
-            0xb7 => "ORA A",
-            0xb8 => "CMP B",
-            0xb9 => "CMP C",
-            0xba => "CMP D",
-            0xbb => "CMP E",
-            0xbc => "CMP H",
-            0xbd => "CMP L",
-            0xbe => "CMP M",
-            0xbf => "CMP A",
-            0xc0 => "RNZ",
-            0xc1 => "POP B",
-            0xc2 => "JNZ",
-            0xc3 => "JMP",
-            0xc4 => "CNZ",
-            0xc5 => "PUSH B",
-            0xc6 => "ADI",
-            0xc7 => "RST 0",
-            0xc8 => "RZ",
-            0xc9 => "RET",
-            0xca => "JZ",
-            0xcb => "0xcb",
-            0xcc => "CZ",
-            0xcd => "CALL",
-            0xce => "ACI",
-            0xcf => "RST 1",
-            0xd0 => "RNC",
-            0xd1 => "POP D",
-            0xd2 => "JNC",
-            0xd3 => "OUT",
-            0xd4 => "CNC",
-            0xd5 => "PUSH D",
-            0xd6 => "SUI",
-            0xd7 => "RST 2",
-            0xd8 => "RC",
-            0xd9 => "0xd9",
-            0xda => "JC",
-            0xdb => "IN",
-            0xdc => "CC",
-            0xdd => "0xdd",
-            0xde => "SBI",
-            0xdf => "RST 3",
-            0xe0 => "RPO",
-            0xe1 => "POP H",
-            0xe2 => "JPO",
-            0xe3 => "XTHL",
-            0xe4 => "CPO",
-            0xe5 => "PUSH H",
-            0xe6 => "ANI",
-            0xe7 => "RST 4",
-            0xe8 => "RPE",
-            0xe9 => "PCHL",
-            0xea => "JPE",
-            0xeb => "XCHG",
-            0xec => "CPE",
-            0xed => "0xed",
-            0xee => "XRI",
-            0xef => "RST 5",
-            0xf0 => "RP",
-            0xf1 => "POP PSW",
-            0xf2 => "JP",
-            0xf3 => "DI",
-            0xf4 => "CP",
-            0xf5 => "PUSH PSW",
-            0xf6 => "ORI",
-            0xf7 => "RST 6",
-            0xf8 => "RM",
-            0xf9 => "SPHL",
-            0xfa => "JM",
-            0xfb => "EI",
-            0xfc => "CM",
-            0xfd => "0xfd",
-            0xfe => "CPI",
-            0xff => "RST 7",
+        let info = self.info();
+
+        if info.undocumented {
+            return write!(f, "{:#04x}", self.0);
+        }
+
+        write!(f, "{}", info.mnemonic)?;
+
+        let mut separator = " ";
+        for operand in info.operands {
+            match *operand {
+                Operand::Reg(reg) => write!(f, "{}{}", separator, reg)?,
+                Operand::Pair(pair) => write!(f, "{}{}", separator, pair)?,
+                Operand::Vector(n) => write!(f, "{}{}", separator, n)?,
+                _ => continue,
+            }
+            separator = ", ";
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Reg::B => "B",
+            Reg::C => "C",
+            Reg::D => "D",
+            Reg::E => "E",
+            Reg::H => "H",
+            Reg::L => "L",
+            Reg::M => "M",
+            Reg::A => "A",
         };
 
-        write!(f, "{}", op)
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Pair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Pair::B => "B",
+            Pair::D => "D",
+            Pair::H => "H",
+            Pair::Sp => "SP",
+            Pair::Psw => "PSW",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl BitOr for Flags {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self::Output {
+        self.union(rhs)
+    }
+}
+
+impl fmt::Debug for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(flag, name) in &[(Flags::S, "s"), (Flags::Z, "z"), (Flags::AC, "ac"),
+                               (Flags::P, "p"), (Flags::CY, "cy")] {
+            write!(f, "{}", if self.contains(flag) { name } else { "." })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_match_operands() {
+        for (code, info) in OPCODES.iter().enumerate() {
+            let size = info.operands.iter().fold(1, |size, operand| match *operand {
+                D8 | Port => size + 1,
+                D16 | Addr => size + 2,
+                _ => size,
+            });
+
+            assert_eq!(size, info.size, "{:02x}", code);
+            assert!(info.min_cycles <= info.max_cycles, "{:02x}", code);
+        }
+    }
+
+    #[test]
+    fn conditional_branches_read_flags() {
+        for code in (0xc0..=0xff).filter(|code| code & 0x7 == 0 || code & 0x7 == 2 || code & 0x7 == 4) {
+            let info = Opcode::from(code).info();
+
+            assert_ne!(info.flags_read, Flags::NONE, "{:02x}", code);
+            assert_eq!(info.flags_written, Flags::NONE, "{:02x}", code);
+        }
     }
 }
@@ -0,0 +1,544 @@
+use crate::bus::Bus;
+use crate::cpu::{Cpu, Machine};
+use crate::interrupt::Interrupt;
+use crate::observer::Observer;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Init {
+    Ready,
+    Icw2,
+    Icw3,
+    Icw4,
+}
+
+/// An Intel 8259A programmable interrupt controller in 8080 mode.
+///
+/// The controller is mounted on two consecutive I/O ports: forward the
+/// machine's `input`/`output` calls for those ports to it, with bit 0 of the
+/// port selecting A0. Devices drive the IR0-IR7 lines with `raise` and
+/// `lower`, and the host calls `deliver` between instructions to pass the
+/// resulting CALL to the CPU.
+#[derive(Clone, Debug)]
+pub struct Pic8259 {
+    init: Init,
+    initialized: bool,
+    icw1: u8,
+    vector_high: u8,
+    icw4: u8,
+    imr: u8,
+    irr: u8,
+    isr: u8,
+    lines: u8,
+    lowest: u8,
+    read_isr: bool,
+    poll: bool,
+    special_mask: bool,
+    rotate_on_aeoi: bool,
+    /// The level `deliver` passed to the CPU, with its CALL, until the CPU
+    /// accepts or drops it.
+    delivered: Option<(u8, Interrupt)>,
+}
+
+const ICW1_IC4: u8 = 0x01;
+const ICW1_SNGL: u8 = 0x02;
+const ICW1_ADI: u8 = 0x04;
+const ICW1_LTIM: u8 = 0x08;
+const ICW4_AEOI: u8 = 0x02;
+
+impl Default for Pic8259 {
+    fn default() -> Self {
+        Pic8259 {
+            init: Init::Ready,
+            initialized: false,
+            icw1: 0,
+            vector_high: 0,
+            icw4: 0,
+            imr: 0,
+            irr: 0,
+            isr: 0,
+            lines: 0,
+            lowest: 7,
+            read_isr: false,
+            poll: false,
+            special_mask: false,
+            rotate_on_aeoi: false,
+            delivered: None,
+        }
+    }
+}
+
+impl Pic8259 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drives interrupt request line `irq` high.
+    pub fn raise(&mut self, irq: u8) {
+        let bit = 1 << (irq & 0x7);
+
+        if self.level_triggered() || self.lines & bit == 0 {
+            self.irr |= bit;
+        }
+
+        self.lines |= bit;
+    }
+
+    /// Drives interrupt request line `irq` low.
+    pub fn lower(&mut self, irq: u8) {
+        let bit = 1 << (irq & 0x7);
+
+        if self.level_triggered() {
+            self.irr &= !bit;
+        }
+
+        self.lines &= !bit;
+    }
+
+    /// The state of the INT output to the CPU.
+    pub fn int(&self) -> bool {
+        self.highest_request().is_some()
+    }
+
+    /// Performs the INTA sequence, returning the CALL instruction for the
+    /// highest priority request and marking it as in service.
+    pub fn acknowledge(&mut self) -> Option<Interrupt> {
+        let level = self.highest_request()?;
+        self.service(level);
+        Some(Interrupt::call(self.vector(level)))
+    }
+
+    /// Passes the highest priority request to `cpu` if it would accept an
+    /// interrupt now. Returns whether an interrupt was delivered.
+    ///
+    /// The level only goes in service once the CPU has accepted the CALL,
+    /// which is noticed on the next call. A CPU may never accept it, if DI
+    /// runs first or another request takes its place, and then the level
+    /// stays requested.
+    pub fn deliver<B: Bus, O: Observer>(&mut self, cpu: &mut Cpu<B, O>) -> bool {
+        if let Some((level, instruction)) = self.delivered {
+            match cpu.pending_interrupt() {
+                Some(pending) if pending == instruction => return false,
+                Some(_) => self.delivered = None,
+                None => {
+                    self.delivered = None;
+                    self.service(level);
+                }
+            }
+        }
+
+        if !cpu.int_enable || cpu.interrupt_pending() {
+            return false;
+        }
+
+        match self.highest_request() {
+            Some(level) => {
+                let instruction = Interrupt::call(self.vector(level));
+                cpu.request_interrupt(instruction);
+                self.delivered = Some((level, instruction));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn in_service(&self) -> u8 {
+        self.isr
+    }
+
+    pub fn requests(&self) -> u8 {
+        self.irr
+    }
+
+    pub fn mask(&self) -> u8 {
+        self.imr
+    }
+
+    fn level_triggered(&self) -> bool {
+        self.icw1 & ICW1_LTIM != 0
+    }
+
+    fn auto_eoi(&self) -> bool {
+        self.icw4 & ICW4_AEOI != 0
+    }
+
+    /// Levels in priority order, highest first.
+    fn priorities(&self) -> impl Iterator<Item = u8> {
+        let highest = (self.lowest + 1) & 0x7;
+        (0..8).map(move |i| (highest + i) & 0x7)
+    }
+
+    fn highest_request(&self) -> Option<u8> {
+        if !self.initialized || self.init != Init::Ready {
+            return None;
+        }
+
+        let requests = self.irr & !self.imr;
+
+        for level in self.priorities() {
+            let bit = 1 << level;
+
+            // Outside special mask mode a level in service blocks itself and
+            // every lower priority level.
+            if self.isr & bit != 0 && !self.special_mask {
+                return None;
+            }
+
+            if requests & bit != 0 && self.isr & bit == 0 {
+                return Some(level);
+            }
+        }
+
+        None
+    }
+
+    fn vector(&self, level: u8) -> u16 {
+        let low = if self.icw1 & ICW1_ADI != 0 {
+            (self.icw1 & 0xe0) | (level << 2)
+        } else {
+            (self.icw1 & 0xc0) | (level << 3)
+        };
+
+        (self.vector_high as u16) << 8 | low as u16
+    }
+
+    fn service(&mut self, level: u8) {
+        let bit = 1 << level;
+
+        if !self.level_triggered() {
+            self.irr &= !bit;
+        }
+
+        if self.auto_eoi() {
+            if self.rotate_on_aeoi {
+                self.lowest = level;
+            }
+        } else {
+            self.isr |= bit;
+        }
+    }
+
+    fn highest_in_service(&self) -> Option<u8> {
+        self.priorities().find(|level| self.isr & (1 << level) != 0)
+    }
+
+    fn icw1(&mut self, byte: u8) {
+        *self = Pic8259 {
+            icw1: byte,
+            init: Init::Icw2,
+            lines: self.lines,
+            ..Pic8259::default()
+        };
+    }
+
+    fn ocw2(&mut self, byte: u8) {
+        let level = byte & 0x7;
+
+        match byte >> 5 {
+            // Non-specific EOI, optionally rotating.
+            0b001 | 0b101 => {
+                if let Some(level) = self.highest_in_service() {
+                    self.isr &= !(1 << level);
+
+                    if byte >> 5 == 0b101 {
+                        self.lowest = level;
+                    }
+                }
+            }
+            // Specific EOI, optionally rotating.
+            0b011 | 0b111 => {
+                self.isr &= !(1 << level);
+
+                if byte >> 5 == 0b111 {
+                    self.lowest = level;
+                }
+            }
+            0b100 => self.rotate_on_aeoi = true,
+            0b000 => self.rotate_on_aeoi = false,
+            0b110 => self.lowest = level,
+            _ => {}
+        }
+    }
+
+    fn ocw3(&mut self, byte: u8) {
+        if byte & 0x40 != 0 {
+            self.special_mask = byte & 0x20 != 0;
+        }
+
+        if byte & 0x02 != 0 {
+            self.read_isr = byte & 0x01 != 0;
+        }
+
+        self.poll = byte & 0x04 != 0;
+    }
+
+    fn poll_word(&mut self) -> u8 {
+        self.poll = false;
+
+        match self.highest_request() {
+            Some(level) => {
+                self.service(level);
+                0x80 | level
+            }
+            None => 0,
+        }
+    }
+}
+
+impl Machine for Pic8259 {
+    fn input(&mut self, port: u8) -> u8 {
+        if port & 1 == 1 {
+            self.imr
+        } else if self.poll {
+            self.poll_word()
+        } else if self.read_isr {
+            self.isr
+        } else {
+            self.irr
+        }
+    }
+
+    fn output(&mut self, port: u8, byte: u8) {
+        let a0 = port & 1 == 1;
+
+        if !a0 && byte & 0x10 != 0 {
+            return self.icw1(byte);
+        }
+
+        match (a0, self.init) {
+            (true, Init::Icw2) => {
+                self.vector_high = byte;
+                self.init = if self.icw1 & ICW1_SNGL == 0 {
+                    Init::Icw3
+                } else if self.icw1 & ICW1_IC4 != 0 {
+                    Init::Icw4
+                } else {
+                    self.initialized = true;
+                    Init::Ready
+                };
+            }
+            // Cascading isn't modelled; the slave configuration is ignored.
+            (true, Init::Icw3) => {
+                self.init = if self.icw1 & ICW1_IC4 != 0 {
+                    Init::Icw4
+                } else {
+                    self.initialized = true;
+                    Init::Ready
+                };
+            }
+            (true, Init::Icw4) => {
+                self.icw4 = byte;
+                self.initialized = true;
+                self.init = Init::Ready;
+            }
+            (true, Init::Ready) => self.imr = byte,
+            (false, _) if byte & 0x08 == 0 => self.ocw2(byte),
+            (false, _) => self.ocw3(byte),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ICW1: single, interval 4, ICW4 needed. ICW2: vectors at 0x2000.
+    fn pic() -> Pic8259 {
+        let mut pic = Pic8259::new();
+        pic.output(0, 0x17);
+        pic.output(1, 0x20);
+        pic.output(1, 0x00);
+        pic
+    }
+
+    fn vector(pic: &mut Pic8259) -> Option<u16> {
+        pic.acknowledge().map(|call| {
+            let [low, high] = call.operands();
+            (high as u16) << 8 | low as u16
+        })
+    }
+
+    #[test]
+    fn uninitialized_is_silent() {
+        let mut pic = Pic8259::new();
+        pic.raise(0);
+
+        assert!(!pic.int());
+        assert_eq!(pic.acknowledge(), None);
+    }
+
+    #[test]
+    fn call_vectors() {
+        let mut pic = pic();
+        pic.raise(3);
+
+        assert!(pic.int());
+        assert_eq!(pic.acknowledge(), Some(Interrupt::call(0x200c)));
+        assert_eq!(pic.in_service(), 0x08);
+
+        let mut pic = Pic8259::new();
+        // Interval 8 with A7-A6 set.
+        pic.output(0, 0xd2);
+        pic.output(1, 0x30);
+        pic.raise(2);
+
+        assert_eq!(vector(&mut pic), Some(0x30d0));
+    }
+
+    #[test]
+    fn priority_and_nesting() {
+        let mut pic = pic();
+        pic.raise(5);
+        pic.raise(2);
+
+        assert_eq!(vector(&mut pic), Some(0x2008));
+        // IR5 is blocked by the IR2 in service.
+        assert!(!pic.int());
+
+        pic.raise(1);
+        assert_eq!(vector(&mut pic), Some(0x2004));
+
+        // Non-specific EOI clears IR1, then IR2.
+        pic.output(0, 0x20);
+        assert!(!pic.int());
+        pic.output(0, 0x20);
+        assert_eq!(vector(&mut pic), Some(0x2014));
+    }
+
+    #[test]
+    fn masking() {
+        let mut pic = pic();
+        pic.output(1, 0x04);
+        pic.raise(2);
+
+        assert!(!pic.int());
+        assert_eq!(pic.input(1), 0x04);
+
+        pic.output(1, 0x00);
+        assert_eq!(vector(&mut pic), Some(0x2008));
+    }
+
+    #[test]
+    fn edge_and_level_triggering() {
+        let mut pic = pic();
+        pic.raise(4);
+        vector(&mut pic);
+        pic.output(0, 0x20);
+
+        // The line is still high but there has been no new edge.
+        assert!(!pic.int());
+        pic.lower(4);
+        pic.raise(4);
+        assert!(pic.int());
+
+        let mut pic = Pic8259::new();
+        pic.output(0, 0x1e);
+        pic.output(1, 0x20);
+        pic.raise(4);
+        vector(&mut pic);
+        pic.output(0, 0x20);
+
+        assert!(pic.int());
+        pic.lower(4);
+        assert!(!pic.int());
+    }
+
+    #[test]
+    fn rotation_and_specific_eoi() {
+        let mut pic = pic();
+        pic.raise(0);
+        pic.raise(1);
+
+        assert_eq!(vector(&mut pic), Some(0x2000));
+        // Rotate on specific EOI: IR0 becomes the lowest priority.
+        pic.output(0, 0xe0);
+        pic.lower(0);
+        pic.raise(0);
+
+        assert_eq!(vector(&mut pic), Some(0x2004));
+        pic.output(0, 0x61);
+        assert_eq!(vector(&mut pic), Some(0x2000));
+    }
+
+    #[test]
+    fn read_registers_and_poll() {
+        let mut pic = pic();
+        pic.raise(6);
+
+        assert_eq!(pic.input(0), 0x40);
+
+        pic.output(0, 0x0c);
+        assert_eq!(pic.input(0), 0x86);
+
+        pic.output(0, 0x0b);
+        assert_eq!(pic.input(0), 0x40);
+    }
+
+    #[test]
+    fn delivers_to_cpu() {
+        struct Facade;
+
+        impl Machine for Facade {
+            fn input(&mut self, _: u8) -> u8 { 0 }
+            fn output(&mut self, _: u8, _: u8) {}
+        }
+
+        let mut cpu = Cpu::new();
+        let mut pic = pic();
+        cpu.sp = 0x100u16.into();
+        pic.raise(7);
+
+        assert!(!pic.deliver(&mut cpu));
+
+        cpu.int_enable = true;
+        assert!(pic.deliver(&mut cpu));
+        assert_eq!(pic.in_service(), 0);
+        cpu.emulate(&mut Facade).unwrap();
+
+        assert!(!pic.deliver(&mut cpu));
+        assert_eq!(*cpu.pc, 0x201c);
+        assert_eq!(pic.in_service(), 0x80);
+    }
+
+    #[test]
+    fn undelivered_requests_stay_pending() {
+        struct Facade;
+
+        impl Machine for Facade {
+            fn input(&mut self, _: u8) -> u8 { 0 }
+            fn output(&mut self, _: u8, _: u8) {}
+        }
+
+        let program = crate::asm::assemble("EI\nDI\nEI\nNOP\nNOP").unwrap();
+        let mut cpu = Cpu::new();
+        let mut pic = pic();
+        cpu.load_into_rom(&program.bytes, 0);
+        cpu.sp = 0x100u16.into();
+        pic.raise(5);
+
+        // DI runs in the delay after EI, before the CALL is accepted.
+        cpu.emulate(&mut Facade).unwrap();
+        assert!(pic.deliver(&mut cpu));
+        cpu.emulate(&mut Facade).unwrap();
+        assert!(!pic.deliver(&mut cpu));
+        assert_eq!(pic.in_service(), 0);
+
+        // Another request takes the place of the CALL.
+        cpu.request_interrupt(Interrupt::rst(1));
+        cpu.emulate(&mut Facade).unwrap();
+        cpu.emulate(&mut Facade).unwrap();
+        assert!(!pic.deliver(&mut cpu));
+        cpu.emulate(&mut Facade).unwrap();
+
+        assert_eq!(*cpu.pc, 0x08);
+        assert_eq!(pic.in_service(), 0);
+        assert_eq!(pic.requests(), 0x20);
+
+        cpu.int_enable = true;
+        assert!(pic.deliver(&mut cpu));
+        cpu.emulate(&mut Facade).unwrap();
+        pic.deliver(&mut cpu);
+
+        assert_eq!(*cpu.pc, 0x2014);
+        assert_eq!(pic.in_service(), 0x20);
+    }
+}
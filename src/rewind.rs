@@ -0,0 +1,212 @@
+use std::collections::VecDeque;
+
+/// A snapshot stored as the XOR of it and the next newer snapshot, run length
+/// encoded. Save states of the same machine differ in few bytes, so most of
+/// the XOR is zero.
+#[derive(Clone, Debug)]
+struct Delta {
+    position: u64,
+    len: usize,
+    runs: Vec<u8>,
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+
+    out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+
+    while let Some((&byte, rest)) = data.split_first() {
+        *data = rest;
+        value |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    value
+}
+
+/// Encodes `old ^ new` as pairs of a count of zero bytes and a run of literal
+/// bytes. The shorter state is treated as if padded with zeros.
+fn encode(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let len = old.len().max(new.len());
+    let xor = |i: usize| old.get(i).copied().unwrap_or(0) ^ new.get(i).copied().unwrap_or(0);
+    let mut runs = Vec::new();
+    let mut i = 0;
+
+    while i < len {
+        let zeros = (i..len).take_while(|&j| xor(j) == 0).count();
+        let literal = (i + zeros..len).take_while(|&j| xor(j) != 0).count();
+
+        write_varint(&mut runs, zeros);
+        write_varint(&mut runs, literal);
+        runs.extend((i + zeros..i + zeros + literal).map(xor));
+        i += zeros + literal;
+    }
+
+    runs
+}
+
+/// Recovers the older snapshot from `delta` and the newer one.
+fn decode(new: &[u8], delta: &Delta) -> Vec<u8> {
+    let mut state = new.to_vec();
+    let mut runs = &delta.runs[..];
+    let mut i = 0;
+
+    state.resize(state.len().max(delta.len), 0);
+
+    while !runs.is_empty() {
+        i += read_varint(&mut runs);
+        let literal = read_varint(&mut runs);
+        let (bytes, rest) = runs.split_at(literal);
+
+        for (byte, xor) in state[i..i + literal].iter_mut().zip(bytes) {
+            *byte ^= xor;
+        }
+
+        i += literal;
+        runs = rest;
+    }
+
+    state.truncate(delta.len);
+    state
+}
+
+/// A bounded history of save states for stepping backwards. Each state is
+/// tagged with a position, such as an instruction or cycle count, that only
+/// increases. Only the newest state is kept whole; older ones are deltas.
+#[derive(Clone, Debug, Default)]
+pub struct Rewind {
+    capacity: usize,
+    interval: u64,
+    latest: Option<(u64, Vec<u8>)>,
+    deltas: VecDeque<Delta>,
+}
+
+impl Rewind {
+    /// Keeps up to `capacity` states, taken at least `interval` apart when
+    /// the owner checks `due`.
+    pub fn new(capacity: usize, interval: u64) -> Self {
+        Rewind { capacity, interval, latest: None, deltas: VecDeque::new() }
+    }
+
+    /// Whether a state should be pushed at `position`.
+    pub fn due(&self, position: u64) -> bool {
+        self.latest.as_ref().is_none_or(|&(latest, _)| position >= latest + self.interval)
+    }
+
+    pub fn push(&mut self, position: u64, state: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if let Some((previous, old)) = self.latest.replace((position, state)) {
+            let new = &self.latest.as_ref().unwrap().1;
+            let runs = encode(&old, new);
+            self.deltas.push_back(Delta { position: previous, len: old.len(), runs });
+        }
+
+        if self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    /// Removes the newest state.
+    pub fn pop(&mut self) -> Option<(u64, Vec<u8>)> {
+        let (position, state) = self.latest.take()?;
+
+        if let Some(delta) = self.deltas.pop_back() {
+            self.latest = Some((delta.position, decode(&state, &delta)));
+        }
+
+        Some((position, state))
+    }
+
+    /// Discards every state after `position` and returns the newest one left,
+    /// or returns `None` without discarding anything if no state is that old.
+    pub fn seek(&mut self, position: u64) -> Option<(u64, &[u8])> {
+        let oldest = self.deltas.front().map(|delta| delta.position)
+            .or(self.latest.as_ref().map(|&(position, _)| position))?;
+
+        if oldest > position {
+            return None;
+        }
+
+        while self.latest.as_ref().is_some_and(|&(latest, _)| latest > position) {
+            self.pop();
+        }
+
+        self.latest.as_ref().map(|(position, state)| (*position, &state[..]))
+    }
+
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// The bytes held, as a measure of how well the deltas compress.
+    pub fn size(&self) -> usize {
+        let latest = self.latest.as_ref().map_or(0, |(_, state)| state.len());
+        latest + self.deltas.iter().map(|delta| delta.runs.len()).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deltas_round_trip() {
+        let old = (0..300u32).map(|i| i as u8).collect::<Vec<_>>();
+        let mut new = old.clone();
+        new[5] = 0xff;
+        new[200..210].fill(0);
+        new.extend([1, 2, 3]);
+
+        let delta = Delta { position: 0, len: old.len(), runs: encode(&old, &new) };
+        assert!(delta.runs.len() < 30);
+        assert_eq!(decode(&new, &delta), old);
+
+        let delta = Delta { position: 0, len: new.len(), runs: encode(&new, &old) };
+        assert_eq!(decode(&old, &delta), new);
+    }
+
+    #[test]
+    fn push_pop_and_seek() {
+        let mut rewind = Rewind::new(3, 10);
+        let states = (0..4u8).map(|i| vec![i; 100]).collect::<Vec<_>>();
+
+        assert!(rewind.due(0));
+        for (i, state) in states.iter().enumerate() {
+            rewind.push(i as u64 * 10, state.clone());
+        }
+
+        assert!(!rewind.due(35));
+        assert!(rewind.due(40));
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.seek(5), None);
+        assert_eq!(rewind.seek(25), Some((20, &states[2][..])));
+        assert_eq!(rewind.pop(), Some((20, states[2].clone())));
+        assert_eq!(rewind.pop(), Some((10, states[1].clone())));
+        assert_eq!(rewind.pop(), None);
+        assert!(rewind.is_empty());
+    }
+}
@@ -0,0 +1,143 @@
+use std::fmt::Write;
+
+use crate::image::{hex_bytes, Image, ImageError};
+
+const RECORD_LEN: usize = 16;
+
+fn record(out: &mut String, kind: char, address: &[u8], data: &[u8]) {
+    let count = (address.len() + data.len() + 1) as u8;
+    let mut sum = count;
+
+    write!(out, "S{}{:02X}", kind, count).unwrap();
+
+    for &byte in address.iter().chain(data) {
+        sum = sum.wrapping_add(byte);
+        write!(out, "{:02X}", byte).unwrap();
+    }
+
+    writeln!(out, "{:02X}", !sum).unwrap();
+}
+
+/// Encodes `image` as Motorola S-records: an S0 header, S1 data, an S5
+/// record count and an S9 record holding the start address.
+pub fn encode(image: &Image) -> String {
+    let mut out = String::new();
+    let mut count = 0u16;
+
+    record(&mut out, '0', &[0, 0], &[]);
+
+    for segment in &image.segments {
+        for (i, chunk) in segment.data.chunks(RECORD_LEN).enumerate() {
+            let address = segment.address.wrapping_add((i * RECORD_LEN) as u16);
+            record(&mut out, '1', &address.to_be_bytes(), chunk);
+            count = count.wrapping_add(1);
+        }
+    }
+
+    record(&mut out, '5', &count.to_be_bytes(), &[]);
+    record(&mut out, '9', &image.start.unwrap_or(0).to_be_bytes(), &[]);
+    out
+}
+
+/// Parses S-records. S2/S3 data is accepted while it lies in the first 64K.
+pub fn parse(text: &str) -> Result<Image, ImageError> {
+    let mut image = Image::default();
+
+    for (index, record) in text.lines().enumerate() {
+        let line = index + 1;
+        let record = record.trim();
+
+        if record.is_empty() {
+            continue;
+        }
+
+        let (kind, bytes) = match record.strip_prefix('S').and_then(|rest| rest.get(..1)) {
+            Some(kind) => (kind, hex_bytes(&record[2..], line)?),
+            None => return Err(ImageError::Syntax { line }),
+        };
+
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err(ImageError::Syntax { line });
+        }
+
+        let (body, checksum) = bytes.split_at(bytes.len() - 1);
+        let expected = !body.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+        if checksum[0] != expected {
+            return Err(ImageError::Checksum { line, expected, found: checksum[0] });
+        }
+
+        let address_len = match kind {
+            "0" | "1" | "5" | "9" => 2,
+            "2" | "6" | "8" => 3,
+            "3" | "7" => 4,
+            _ => return Err(ImageError::UnsupportedRecord { line, record: format!("S{}", kind) }),
+        };
+
+        if body.len() < 1 + address_len {
+            return Err(ImageError::Syntax { line });
+        }
+
+        let address = body[1..=address_len]
+            .iter()
+            .fold(0u32, |address, &byte| address << 8 | byte as u32);
+        let data = &body[1 + address_len..];
+
+        match kind {
+            "1" | "2" | "3" => {
+                if address as usize + data.len() > 0x10000 {
+                    return Err(ImageError::OutOfRange { line });
+                }
+
+                image.push(address as u16, data);
+            }
+            "7" | "8" | "9" => {
+                if address > 0xffff {
+                    return Err(ImageError::OutOfRange { line });
+                }
+
+                image.start = Some(address as u16);
+                return Ok(image);
+            }
+            _ => {}
+        }
+    }
+
+    Err(ImageError::MissingEnd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_records() {
+        let mut image = Image::new(0x100, vec![0x3e, 0x42]);
+        image.start = Some(0x100);
+
+        assert_eq!(encode(&image), "\
+            S0030000FC\n\
+            S10501003E4279\n\
+            S5030001FB\n\
+            S9030100FB\n");
+    }
+
+    #[test]
+    fn parse_round_trips() {
+        let mut image = Image::new(0x100, (0..40).collect());
+        image.push(0x2000, &[0xff; 3]);
+        image.start = Some(0x100);
+
+        assert_eq!(parse(&encode(&image)), Ok(image));
+        assert_eq!(parse("S2060001003E4278\nS804000100FA").unwrap().segments[0].address, 0x100);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse("S10501003E4278\nS9030000FC"),
+                   Err(ImageError::Checksum { line: 1, expected: 0x79, found: 0x78 }));
+        assert_eq!(parse("S10501003E4279"), Err(ImageError::MissingEnd));
+        assert_eq!(parse("S2060100003E4278"), Err(ImageError::OutOfRange { line: 1 }));
+        assert_eq!(parse("X1"), Err(ImageError::Syntax { line: 1 }));
+    }
+}
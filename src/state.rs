@@ -0,0 +1,316 @@
+use std::error;
+use std::fmt;
+
+/// Identifies a save state file.
+pub const MAGIC: &[u8; 8] = b"I8080SAV";
+
+/// The current format version, which `load_state` requires. Bump it whenever
+/// the layout changes.
+pub const VERSION: u16 = 2;
+
+/// The magic number, version, payload length and payload checksum.
+const HEADER_LEN: usize = 18;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// The data isn't a save state.
+    BadMagic,
+    UnsupportedVersion(u16),
+    Checksum { expected: u32, found: u32 },
+    /// The data ended before the state was complete.
+    Truncated,
+    /// The state was read but holds a value that can't be restored, or data
+    /// is left over.
+    Invalid,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}, expected {}", version, VERSION)
+            }
+            StateError::Checksum { expected, found } => {
+                write!(f, "save state checksum is {:08X}, expected {:08X}", found, expected)
+            }
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid => write!(f, "save state is invalid"),
+        }
+    }
+}
+
+impl error::Error for StateError {}
+
+/// State kept by a `Machine` outside the CPU and memory, such as latches and
+/// shift registers, so that `Cpu::save_state` can store it alongside the
+/// CPU.
+pub trait MachineState {
+    fn save_state(&self, writer: &mut Writer);
+
+    /// Restores the state written by `save_state`. `Cpu::load_state` puts
+    /// the machine back as it was if this fails, so it may stop part way.
+    fn load_state(&mut self, reader: &mut Reader) -> Result<(), StateError>;
+}
+
+impl MachineState for () {
+    fn save_state(&self, _: &mut Writer) {}
+
+    fn load_state(&mut self, _: &mut Reader) -> Result<(), StateError> {
+        Ok(())
+    }
+}
+
+/// Builds a save state. Values are little endian.
+#[derive(Default)]
+pub struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Wraps the data written in the header.
+    pub(crate) fn finish(self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(HEADER_LEN + self.data.len());
+
+        state.extend_from_slice(MAGIC);
+        state.extend_from_slice(&VERSION.to_le_bytes());
+        state.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        state.extend_from_slice(&crc32(&self.data).to_le_bytes());
+        state.extend_from_slice(&self.data);
+        state
+    }
+}
+
+/// Reads back the values of a `Writer` in the same order.
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Checks the header of `state`, returning a reader for its payload.
+    pub(crate) fn new(state: &'a [u8]) -> Result<Self, StateError> {
+        if !state.starts_with(MAGIC) {
+            return Err(StateError::BadMagic);
+        }
+
+        let mut header = Reader { data: &state[MAGIC.len()..] };
+        let version = header.u16()?;
+
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let len = u32::from_le_bytes(header.array()?) as usize;
+        let expected = u32::from_le_bytes(header.array()?);
+        let data = header.data.get(..len).ok_or(StateError::Truncated)?;
+        let found = crc32(data);
+
+        if header.data.len() != len {
+            return Err(StateError::Invalid);
+        }
+
+        if found != expected {
+            return Err(StateError::Checksum { expected, found });
+        }
+
+        Ok(Reader { data })
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub(crate) fn finish(self) -> Result<(), StateError> {
+        if self.data.is_empty() { Ok(()) } else { Err(StateError::Invalid) }
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+}
+
+/// The CRC-32 used by zip and PNG.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            if crc & 1 != 0 { crc >> 1 ^ 0xedb8_8320 } else { crc >> 1 }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, Cpu, CpuError, Interrupt, Machine};
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Latch {
+        value: u8,
+        shift: u16,
+    }
+
+    impl Machine for Latch {
+        fn input(&mut self, _: u8) -> u8 { self.value }
+        fn output(&mut self, _: u8, value: u8) { self.value = value }
+    }
+
+    impl MachineState for Latch {
+        fn save_state(&self, writer: &mut Writer) {
+            writer.u8(self.value);
+            writer.u16(self.shift);
+        }
+
+        fn load_state(&mut self, reader: &mut Reader) -> Result<(), StateError> {
+            self.value = reader.u8()?;
+            self.shift = reader.u16()?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn round_trip() {
+        let program = asm::assemble("EI\nLXI SP,100H\nMVI A,42H\nOUT 1\nSTA 3000H\nHLT").unwrap();
+        let mut cpu = Cpu::new();
+        let mut latch = Latch { value: 0, shift: 0x1234 };
+
+        cpu.memory.map_rom(0x0000..0x1000);
+        cpu.load_into_rom(&program.bytes, 0);
+
+        for _ in 0..6 {
+            cpu.emulate(&mut latch).unwrap();
+        }
+
+        cpu.request_interrupt(Interrupt::rst(2));
+        let state = cpu.save_state(&latch);
+
+        let mut restored = Cpu::new();
+        let mut restored_latch = Latch::default();
+        restored.memory.map_rom(0x0000..0x1000);
+        restored.load_state(&mut restored_latch, &state).unwrap();
+
+        assert_eq!(restored_latch, latch);
+        assert_eq!(format!("{:?}", restored), format!("{:?}", cpu));
+        assert_eq!(restored.memory.memory, cpu.memory.memory);
+        assert!(restored.halted && restored.int_enable && restored.interrupt_pending());
+        assert_eq!(restored.cycles, cpu.cycles);
+
+        assert_eq!(restored.emulate(&mut restored_latch), cpu.emulate(&mut latch));
+        assert_eq!(*restored.pc, 0x10);
+    }
+
+    #[test]
+    fn rejects_damaged_states() {
+        let cpu = Cpu::new();
+        let state = cpu.save_state(&());
+        let mut target = Cpu::new();
+
+        let mut corrupt = state.clone();
+        corrupt[HEADER_LEN + 3] ^= 1;
+        assert!(matches!(target.load_state(&mut (), &corrupt), Err(StateError::Checksum { .. })));
+
+        let mut version = state.clone();
+        version[8] = 1;
+        assert_eq!(target.load_state(&mut (), &version), Err(StateError::UnsupportedVersion(1)));
+
+        assert_eq!(target.load_state(&mut (), &state[..100]), Err(StateError::Truncated));
+        assert_eq!(target.load_state(&mut (), b"not a state"), Err(StateError::BadMagic));
+        assert_eq!(target.load_state(&mut Latch::default(), &state), Err(StateError::Truncated));
+    }
+
+    #[test]
+    fn failed_load_changes_nothing() {
+        let program = asm::assemble("LXI SP,100H\nMVI A,42H\nSTA 3000H\nHLT").unwrap();
+        let mut cpu = Cpu::new();
+        let mut latch = Latch { value: 7, shift: 0x1234 };
+        cpu.load_into_rom(&program.bytes, 0);
+
+        for _ in 0..4 {
+            cpu.emulate(&mut latch).unwrap();
+        }
+
+        let before = (format!("{:?}", cpu), cpu.memory.memory.clone(), cpu.cycles);
+        let mut other = Cpu::new();
+        other.a = 0x99u8.into();
+        other.memory.load(&[0xff], 0x3000);
+
+        // The first lacks the latch's part and the second has data left over.
+        let short = other.save_state(&());
+        let long = other.save_state(&Latch::default());
+
+        assert_eq!(cpu.load_state(&mut latch, &short), Err(StateError::Truncated));
+        assert_eq!(cpu.load_state(&mut (), &long), Err(StateError::Invalid));
+        assert_eq!((format!("{:?}", cpu), cpu.memory.memory.clone(), cpu.cycles), before);
+        assert_eq!(latch, Latch { value: 7, shift: 0x1234 });
+    }
+
+    #[test]
+    fn restores_a_pending_trap() {
+        let program = asm::assemble("NOP\nINR A").unwrap();
+        let mut cpu = Cpu::new();
+        cpu.load_into_rom(&program.bytes, 0);
+        let mut latch = Latch::default();
+        cpu.traps.insert(1);
+
+        cpu.emulate(&mut latch).unwrap();
+        assert_eq!(cpu.emulate(&mut latch), Err(CpuError::Trap { pc: 1 }));
+        let state = cpu.save_state(&());
+
+        let mut restored = Cpu::new();
+        restored.traps.insert(1);
+        restored.load_state(&mut (), &state).unwrap();
+
+        assert_eq!(restored.emulate(&mut latch), Ok(5));
+        assert_eq!(*restored.a, 1);
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::ops::RangeInclusive;
+
+use crate::bus::Bus;
+use crate::cpu::Cpu;
+use crate::disasm::Instruction;
+
+/// The state of the CPU before it runs an instruction. Displays as a line of
+/// a trace:
+///
+/// ```text
+/// PC=0100 OP=C30001 A=00 F=02 BC=0000 DE=0000 HL=0000 SP=0000 CYC=0 ; JMP 0100H
+/// ```
+///
+/// Every field before the `;` is `NAME=VALUE` in hex, except the decimal
+/// cycle count, so traces from other emulators can be converted and compared
+/// with `compare`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub pc: u16,
+    pub a: u8,
+    pub flags: u8,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub cycles: u64,
+    /// Whether the instruction was supplied by an interrupting device rather
+    /// than fetched from PC.
+    pub interrupt: bool,
+    bytes: [u8; 3],
+}
+
+impl Entry {
+    /// Captures the CPU before it runs the instruction at PC.
+    pub fn capture<B: Bus, O>(cpu: &Cpu<B, O>) -> Self {
+        let pc = *cpu.pc;
+        Entry::new(cpu, [0, 1, 2].map(|i| cpu.memory.peek(pc.wrapping_add(i))), false)
+    }
+
+    pub(crate) fn new<B, O>(cpu: &Cpu<B, O>, bytes: [u8; 3], interrupt: bool) -> Self {
+        let pair = |high: u8, low: u8| (high as u16) << 8 | low as u16;
+
+        Entry {
+            pc: *cpu.pc,
+            a: *cpu.a,
+            flags: cpu.conditions.into(),
+            bc: pair(*cpu.b, *cpu.c),
+            de: pair(*cpu.d, *cpu.e),
+            hl: pair(*cpu.h, *cpu.l),
+            sp: *cpu.sp,
+            cycles: cpu.cycles,
+            interrupt,
+            bytes,
+        }
+    }
+
+    pub fn instruction(&self) -> Instruction {
+        Instruction::decode(&self.bytes, self.pc).unwrap()
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let instruction = self.instruction();
+
+        write!(f, "PC={:04X} OP=", self.pc)?;
+
+        for byte in instruction.bytes() {
+            write!(f, "{:02X}", byte)?;
+        }
+
+        write!(f,
+               " A={:02X} F={:02X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} CYC={} ; {}",
+               self.a,
+               self.flags,
+               self.bc,
+               self.de,
+               self.hl,
+               self.sp,
+               self.cycles,
+               instruction)?;
+
+        if self.interrupt {
+            write!(f, " (interrupt)")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The last instructions run by a `Cpu` with `history` set, oldest first, for
+/// working out how execution reached an error.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct History {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        History { entries: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    /// Adds `entry`, forgetting the oldest once `capacity` are held.
+    pub fn push(&mut self, entry: Entry) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(entry);
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Entry> + ExactSizeIterator {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl fmt::Display for History {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{}", entry)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a trace of the instructions a `Cpu` runs, one `Entry` per line.
+pub struct Tracer<W> {
+    writer: W,
+    range: RangeInclusive<u16>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(writer: W) -> Self {
+        Tracer { writer, range: 0..=0xffff }
+    }
+
+    /// Only traces instructions starting within `range`.
+    pub fn range(mut self, range: RangeInclusive<u16>) -> Self {
+        self.range = range;
+        self
+    }
+
+    /// Records the instruction at PC, which should be called before each
+    /// `Cpu::emulate`.
+    pub fn record<B: Bus, O>(&mut self, cpu: &Cpu<B, O>) -> io::Result<()> {
+        if !self.range.contains(&*cpu.pc) {
+            return Ok(());
+        }
+
+        writeln!(self.writer, "{}", Entry::capture(cpu))
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// The first line at which two traces disagree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// The line number, counting from one.
+    pub line: usize,
+    /// The lines from each trace, or `None` where a trace ended first.
+    pub expected: Option<String>,
+    pub found: Option<String>,
+    /// The names of the fields that differ.
+    pub fields: Vec<String>,
+}
+
+/// The `NAME=VALUE` fields of a trace line, ignoring any comment after `;`.
+fn fields(line: &str) -> impl Iterator<Item = (&str, &str)> {
+    let line = line.split(';').next().unwrap_or_default();
+    line.split_whitespace().filter_map(|field| field.split_once('='))
+}
+
+/// Compares two traces line by line. Only the fields both lines have are
+/// compared, without regard to case, so a trace without cycle counts can be
+/// checked against one with them.
+pub fn compare<R: BufRead, S: BufRead>(expected: R, found: S) -> io::Result<Option<Divergence>> {
+    let mut expected = expected.lines();
+    let mut found = found.lines();
+    let mut line = 0;
+
+    loop {
+        line += 1;
+
+        let (left, right) = match (expected.next().transpose()?, found.next().transpose()?) {
+            (None, None) => return Ok(None),
+            (Some(left), Some(right)) => (left, right),
+            (expected, found) => {
+                return Ok(Some(Divergence { line, expected, found, fields: Vec::new() }));
+            }
+        };
+
+        let differing = fields(&left)
+            .filter(|(name, value)| {
+                fields(&right).any(|(other, other_value)| {
+                    name.eq_ignore_ascii_case(other) && !value.eq_ignore_ascii_case(other_value)
+                })
+            })
+            .map(|(name, _)| name.to_ascii_uppercase())
+            .collect::<Vec<_>>();
+
+        if !differing.is_empty() {
+            return Ok(Some(Divergence {
+                line,
+                expected: Some(left),
+                found: Some(right),
+                fields: differing,
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, Machine};
+
+    struct Null;
+
+    impl Machine for Null {
+        fn input(&mut self, _: u8) -> u8 { 0 }
+        fn output(&mut self, _: u8, _: u8) {}
+    }
+
+    fn trace(source: &str, range: RangeInclusive<u16>) -> String {
+        let assembly = asm::assemble(source).unwrap();
+        let mut cpu = Cpu::new();
+        let mut tracer = Tracer::new(Vec::new()).range(range);
+        cpu.load_into_rom(&assembly.bytes, assembly.origin);
+        cpu.pc = assembly.origin.into();
+
+        while !cpu.halted {
+            tracer.record(&cpu).unwrap();
+            cpu.emulate(&mut Null).unwrap();
+        }
+
+        String::from_utf8(tracer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn records_state_before_each_instruction() {
+        let source = "ORG 100H\nLXI H,1234H\nCALL SUB\nHLT\nSUB: ADI 80H\nRET";
+
+        assert_eq!(trace(source, 0..=0xffff), "\
+            PC=0100 OP=213412 A=00 F=02 BC=0000 DE=0000 HL=0000 SP=0000 CYC=0 ; LXI H,1234H\n\
+            PC=0103 OP=CD0701 A=00 F=02 BC=0000 DE=0000 HL=1234 SP=0000 CYC=10 ; CALL 0107H\n\
+            PC=0107 OP=C680 A=00 F=02 BC=0000 DE=0000 HL=1234 SP=FFFE CYC=27 ; ADI 80H\n\
+            PC=0109 OP=C9 A=80 F=82 BC=0000 DE=0000 HL=1234 SP=FFFE CYC=34 ; RET\n\
+            PC=0106 OP=76 A=80 F=82 BC=0000 DE=0000 HL=1234 SP=0000 CYC=44 ; HLT\n");
+
+        assert_eq!(trace(source, 0x107..=0x108).lines().count(), 1);
+    }
+
+    #[test]
+    fn history_keeps_the_last_instructions() {
+        let assembly = asm::assemble("EI\nMVI A,1\nINR A\nINR A\nDB 8\nNOP").unwrap();
+        let mut cpu = Cpu::new();
+        cpu.load_into_rom(&assembly.bytes, assembly.origin);
+        cpu.sp = 0x100u16.into();
+        cpu.history = Some(History::new(3));
+
+        for _ in 0..5 {
+            cpu.emulate(&mut Null).unwrap();
+        }
+
+        cpu.request_interrupt(0xcf);
+        cpu.emulate(&mut Null).unwrap();
+
+        let history = cpu.history.as_ref().unwrap();
+        assert_eq!(history.iter().map(|entry| entry.pc).collect::<Vec<_>>(), [4, 5, 6]);
+        assert_eq!(history.to_string(), "\
+            PC=0004 OP=3C A=02 F=02 BC=0000 DE=0000 HL=0000 SP=0100 CYC=16 ; INR A\n\
+            PC=0005 OP=08 A=03 F=06 BC=0000 DE=0000 HL=0000 SP=0100 CYC=21 ; DB 08H\n\
+            PC=0006 OP=CF A=03 F=06 BC=0000 DE=0000 HL=0000 SP=0100 CYC=25 ; RST 1 (interrupt)\n");
+    }
+
+    #[test]
+    fn compare_reports_first_divergence() {
+        let expected = "PC=0100 A=00 F=02 CYC=0 ; NOP\nPC=0101 A=00 F=02 CYC=4\nPC=0102 A=01";
+        let found = "pc=0100 a=00 f=02\nPC=0101 A=00 F=06 ; INR A\n";
+
+        assert_eq!(compare(expected.as_bytes(), expected.as_bytes()).unwrap(), None);
+        assert_eq!(compare(expected.as_bytes(), found.as_bytes()).unwrap(), Some(Divergence {
+            line: 2,
+            expected: Some(String::from("PC=0101 A=00 F=02 CYC=4")),
+            found: Some(String::from("PC=0101 A=00 F=06 ; INR A")),
+            fields: vec![String::from("F")],
+        }));
+        let truncated = &found.as_bytes()[..18];
+        assert_eq!(compare(expected.as_bytes(), truncated).unwrap(), Some(Divergence {
+            line: 2,
+            expected: Some(String::from("PC=0101 A=00 F=02 CYC=4")),
+            found: None,
+            fields: Vec::new(),
+        }));
+    }
+}
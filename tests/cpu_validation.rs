@@ -0,0 +1,121 @@
+//! Runs the standard 8080 exercisers under the CP/M layer and checks what
+//! they print. `cpudiag.bin` ships with the repository; the others are read
+//! from `tests/roms`, and their tests fail if they aren't there.
+
+use i8080::cpm::Cpm;
+use i8080::Cpu;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+fn roms() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("roms")
+}
+
+/// Runs `program` until it exits, returning its console output. Panics if it
+/// takes more than `max_cycles`, so a program stuck in a loop fails rather
+/// than hangs.
+fn run(program: &[u8], max_cycles: u64) -> String {
+    let mut cpu = Cpu::new();
+    let mut cpm = Cpm::with_console(roms(), io::empty(), Vec::new());
+    cpm.load(&mut cpu, program, "");
+
+    while cpm.step(&mut cpu).unwrap() {
+        if cpu.cycles > max_cycles {
+            panic!("still running after {} cycles:\n{}",
+                   max_cycles,
+                   String::from_utf8_lossy(cpm.output()));
+        }
+    }
+
+    String::from_utf8_lossy(cpm.output()).into_owned()
+}
+
+/// Runs the named program from `tests/roms`, failing if it isn't there.
+fn run_rom(name: &str, max_cycles: u64) -> String {
+    match fs::read(roms().join(name)) {
+        Ok(program) => run(&program, max_cycles),
+        Err(error) => panic!("{}: {} (see tests/roms/README.md)",
+                             roms().join(name).display(),
+                             error),
+    }
+}
+
+#[test]
+fn cpudiag() {
+    let program = fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("cpudiag.bin")).unwrap();
+    let output = run(&program, 100_000);
+
+    assert!(output.contains("CPU IS OPERATIONAL"), "{}", output);
+}
+
+#[test]
+fn tst8080() {
+    let output = run_rom("TST8080.COM", 100_000);
+    assert!(output.contains("CPU IS OPERATIONAL"), "{}", output);
+}
+
+#[test]
+fn pre8080() {
+    let output = run_rom("8080PRE.COM", 1_000_000);
+    assert!(output.contains("8080 Preliminary tests complete"), "{}", output);
+}
+
+#[test]
+fn cputest() {
+    let output = run_rom("CPUTEST.COM", 1_000_000_000);
+    assert!(output.contains("CPU TESTS OK"), "{}", output);
+}
+
+/// 8080EXM prints a CRC of the results of every instruction in each group,
+/// after `crc is:` if it matches the one recorded on real hardware or after
+/// `found:` if not. Both are checked against those hardware CRCs here, so a
+/// group can't pass by its line alone. It runs for billions of cycles, so it
+/// is ignored unless asked for:
+///
+/// ```text
+/// cargo test --release --test cpu_validation -- --ignored
+/// ```
+#[test]
+#[ignore = "runs for billions of cycles"]
+fn exm8080() {
+    const CRCS: [(&str, &str); 25] = [
+        ("dad <b,d,h,sp>", "14474ba6"),
+        ("aluop nn", "9e922f9e"),
+        ("aluop <b,c,d,e,h,l,m,a>", "cf762c86"),
+        ("<daa,cma,stc,cmc>", "bb3f030c"),
+        ("<inr,dcr> a", "adb6460e"),
+        ("<inr,dcr> b", "83ed1345"),
+        ("<inx,dcx> b", "f79287cd"),
+        ("<inr,dcr> c", "e5f6721b"),
+        ("<inr,dcr> d", "15b5579a"),
+        ("<inx,dcx> d", "7f4e2501"),
+        ("<inr,dcr> e", "cf2ab396"),
+        ("<inr,dcr> h", "12b2952c"),
+        ("<inx,dcx> h", "9f2b23c0"),
+        ("<inr,dcr> l", "ff57d356"),
+        ("<inr,dcr> m", "92e963bd"),
+        ("<inx,dcx> sp", "d5702fab"),
+        ("lhld nnnn", "a9c3d5cb"),
+        ("shld nnnn", "e8864f26"),
+        ("lxi <b,d,h,sp>,nnnn", "fcf46e12"),
+        ("ldax <b,d>", "2b821d5f"),
+        ("mvi <b,c,d,e,h,l,m,a>,nn", "eaa72044"),
+        ("mov <bcdehla>,<bcdehla>", "10b58cee"),
+        ("sta nnnn / lda nnnn", "ed57af72"),
+        ("<rlc,rrc,ral,rar>", "e0d89235"),
+        ("stax <b,d>", "2b0471e9"),
+    ];
+
+    let output = run_rom("8080EXM.COM", 50_000_000_000);
+    let crcs = output.lines()
+        .filter_map(|line| {
+            let (group, result) = line.split_once("..")?;
+            Some((group, result.rsplit(':').next()?.trim()))
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(crcs, CRCS, "{}", output);
+    assert!(output.contains("Tests complete"), "{}", output);
+}